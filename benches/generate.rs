@@ -0,0 +1,189 @@
+//! Measures parsing a style-sheet, flattening it with its layout, and
+//! generating the C++ code.
+//!
+//! cstylegen is only a binary, so the modules are compiled into the
+//! benchmark directly.
+#![allow(dead_code)]
+
+#[path = "../src/combinator.rs"]
+mod combinator;
+#[path = "../src/errors.rs"]
+mod errors;
+#[path = "../src/layout.rs"]
+mod layout;
+#[path = "../src/model.rs"]
+mod model;
+#[path = "../src/parse/mod.rs"]
+mod parse;
+#[path = "../src/printer/mod.rs"]
+mod printer;
+
+use std::io;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use cssparser::ParserInput;
+
+use combinator::DEFAULT_SEPARATOR;
+use layout::Layout;
+use parse::ParseOptions;
+use printer::{
+    header::generate_header, r#impl::generate_impl, CodeOptions, IndentStyle,
+    Matcher, Printer,
+};
+
+/// Number of top level items in the generated layout (each has 11 colors).
+const ITEMS: usize = 40;
+
+/// Returns a layout and a style-sheet defining all of its colors.
+///
+/// Every item references a definition and mixes plain colors with `var()`
+/// references to variables and other rules.
+fn inputs() -> (String, String) {
+    let mut layout = String::from(
+        "definitions:
+  TabColors:
+    fields:
+      - text
+      - backgrounds: { fields: [regular, hover, pressed] }
+layout:
+",
+    );
+    let mut style = String::from(
+        "@chatterino {
+  author: \"cstylegen\";
+  icon-set: \"dark\";
+}
+:root {
+  --accent: #3498db;
+}
+",
+    );
+    for i in 0..ITEMS {
+        layout += &format!(
+            "  item{i}:
+    fields:
+      - border
+      - background
+      - accentColor
+      - regular: {{ ref: TabColors }}
+      - selected: {{ ref: TabColors }}
+"
+        );
+        style += &format!(
+            "item{i} {{
+  border: #{i:02x}0000;
+  background: rgba(0, 0, {i}, 0.5);
+  accent-color: var(--accent);
+"
+        );
+        for nest in ["regular", "selected"] {
+            style += &format!(
+                "  @nest {nest} {{
+    text: var(--item{i}-border);
+    @nest backgrounds {{
+      regular: #fff;
+      hover: var(--accent);
+      pressed: #80{i:02x}{i:02x}{i:02x};
+    }}
+  }}
+"
+            );
+        }
+        style += "}\n";
+    }
+    (layout, style)
+}
+
+fn options(matcher: Matcher) -> CodeOptions {
+    CodeOptions {
+        class_name: "GeneratedTheme".to_owned(),
+        from_rgb: false,
+        case_insensitive: true,
+        qml: false,
+        eq_operators: false,
+        color_names: false,
+        emit_keys: false,
+        color_array: false,
+        loader: false,
+        preserve_case: false,
+        all_colors: false,
+        matcher,
+        default_includes: true,
+        includes: vec![],
+        designated_init: false,
+        source_comments: false,
+    }
+}
+
+fn parse_theme(style: &str) -> model::Theme<'_> {
+    let mut input = ParserInput::new(style);
+    let mut parser = cssparser::Parser::new(&mut input);
+    let (theme, errors) =
+        parse::parse_recovering(&mut parser, None, ParseOptions::default());
+    assert!(errors.is_empty());
+    theme.expect("the style-sheet should parse")
+}
+
+fn generate(c: &mut Criterion) {
+    let (layout_source, style) = inputs();
+
+    c.bench_function("parse", |b| b.iter(|| parse_theme(black_box(&style))));
+
+    let theme = parse_theme(&style);
+    let layout = Layout::parse_many(&[&layout_source]).unwrap();
+    c.bench_function("flatten", |b| {
+        b.iter(|| {
+            let (flat, replaced) =
+                theme.flatten(DEFAULT_SEPARATOR, false, None).unwrap();
+            assert!(replaced.is_empty());
+            (flat, layout.flatten(false).unwrap())
+        })
+    });
+
+    let (flat, _) = theme.flatten(DEFAULT_SEPARATOR, false, None).unwrap();
+    let flat_layout = layout.flatten(false).unwrap();
+    for (name, matcher) in [("map", Matcher::Map), ("phf", Matcher::Phf)] {
+        let options = options(matcher);
+        c.bench_function(&format!("generate ({name})"), |b| {
+            b.iter(|| {
+                let mut p = Printer::new(io::sink(), IndentStyle::Tabs);
+                generate_impl(
+                    &mut p,
+                    "GeneratedTheme.hpp",
+                    &flat_layout,
+                    &flat,
+                    &options,
+                )
+                .unwrap();
+                generate_header(&mut p, &layout, &flat_layout, &options)
+                    .unwrap();
+                p.finish().unwrap()
+            })
+        });
+    }
+
+    c.bench_function("parse + flatten + generate", |b| {
+        b.iter(|| {
+            let theme = parse_theme(black_box(&style));
+            let (flat, _) =
+                theme.flatten(DEFAULT_SEPARATOR, false, None).unwrap();
+            let layout = Layout::parse_many(&[&layout_source]).unwrap();
+            let flat_layout = layout.flatten(false).unwrap();
+            let options = options(Matcher::Map);
+            let mut p = Printer::new(io::sink(), IndentStyle::Tabs);
+            generate_impl(
+                &mut p,
+                "GeneratedTheme.hpp",
+                &flat_layout,
+                &flat,
+                &options,
+            )
+            .unwrap();
+            generate_header(&mut p, &layout, &flat_layout, &options).unwrap();
+            p.finish().unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, generate);
+criterion_main!(benches);
@@ -0,0 +1,138 @@
+use cssparser::{CowRcStr, RGBA};
+
+use crate::model::{ChatterinoMeta, FlatTheme};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("[line {0}] Expected '@meta' or '@colors' before any values")]
+    MissingSection(usize),
+    #[error("[line {0}] Unknown section '{1}'")]
+    UnknownSection(usize, String),
+    #[error("[line {0}] Expected 'key=value'")]
+    InvalidLine(usize),
+    #[error("[line {0}] '{1}' isn't a color (expected #aarrggbb or #rrggbb)")]
+    InvalidColor(usize, String),
+    #[error("[line {0}] '{1}' needs at least two parts (e.g. 'tabs.text')")]
+    TopLevelColor(usize, String),
+    #[error("'{0}' is both a color and contains other colors")]
+    ConflictingPath(String),
+    #[error("Missing '{0}' in @meta")]
+    MissingMetaItem(&'static str),
+}
+
+enum Section {
+    Meta,
+    Colors,
+}
+
+/// Reads a `c2theme` (the output of [`crate::printer::theme::generate`]).
+///
+/// Empty lines and lines starting with `#` are ignored.
+pub fn parse(source: &str) -> Result<FlatTheme<'_>, ParseError> {
+    let mut section = None;
+    let mut author = None;
+    let mut icon_set = None;
+    let mut meta = ChatterinoMeta {
+        author: CowRcStr::from(""),
+        icon_set: CowRcStr::from(""),
+        name: None,
+        version: None,
+        description: None,
+        extras: Default::default(),
+        ignored: vec![],
+    };
+    let mut rules = ahash::AHashMap::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('@') {
+            section = match name {
+                "meta" => Some(Section::Meta),
+                "colors" => Some(Section::Colors),
+                _ => {
+                    return Err(ParseError::UnknownSection(
+                        line_no,
+                        name.to_owned(),
+                    ))
+                }
+            };
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(ParseError::InvalidLine(line_no));
+        };
+        match section {
+            Some(Section::Meta) => {
+                let text = Some(CowRcStr::from(value));
+                match key {
+                    "author" => author = text,
+                    "iconset" => icon_set = text,
+                    "name" => meta.name = text,
+                    "version" => meta.version = text,
+                    "description" => meta.description = text,
+                    // newer versions of the format might add more
+                    _ => {
+                        meta.extras.insert(key.to_owned(), value.to_owned());
+                    }
+                }
+            }
+            Some(Section::Colors) => {
+                if !key.contains('.') {
+                    return Err(ParseError::TopLevelColor(
+                        line_no,
+                        key.to_owned(),
+                    ));
+                }
+                let Some(color) = parse_color(value) else {
+                    return Err(ParseError::InvalidColor(
+                        line_no,
+                        value.to_owned(),
+                    ));
+                };
+                rules.insert(key.to_owned(), color);
+            }
+            None => return Err(ParseError::MissingSection(line_no)),
+        }
+    }
+
+    // a path can't be a color and a block at the same time
+    for path in rules.keys() {
+        let mut prefixes = path.match_indices('.').map(|(i, _)| &path[..i]);
+        if let Some(prefix) = prefixes.find(|p| rules.contains_key(*p)) {
+            return Err(ParseError::ConflictingPath(prefix.to_owned()));
+        }
+    }
+
+    meta.author = author.ok_or(ParseError::MissingMetaItem("author"))?;
+    meta.icon_set = icon_set.ok_or(ParseError::MissingMetaItem("iconset"))?;
+    Ok(FlatTheme {
+        meta,
+        rules,
+        sources: Default::default(),
+    })
+}
+
+/// Parses `#aarrggbb` or `#rrggbb`.
+fn parse_color(value: &str) -> Option<RGBA> {
+    let hex = value.strip_prefix('#')?;
+    if !hex.is_ascii() {
+        return None;
+    }
+    let channel =
+        |i: usize| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok();
+    match hex.len() {
+        8 => Some(RGBA::new(
+            channel(1)?,
+            channel(2)?,
+            channel(3)?,
+            channel(0)?,
+        )),
+        6 => Some(RGBA::new(channel(0)?, channel(1)?, channel(2)?, 255)),
+        _ => None,
+    }
+}
@@ -1,14 +1,27 @@
-pub fn combine_path(prefix: &str, suffix: &str) -> String {
+/// The separator Chatterino uses between the parts of a path
+/// (`tabs.regular.text`).
+pub const DEFAULT_SEPARATOR: &str = ".";
+
+/// Appends `suffix` to `prefix`, removing `-` and `_` from it.
+///
+/// Unless `preserve_case` is set, `suffix` is lowercased as well.
+pub fn combine_path(
+    prefix: &str,
+    suffix: &str,
+    separator: &str,
+    preserve_case: bool,
+) -> String {
     // TODO: avoid allocating another string
     let suffix = suffix
         .chars()
         .filter_map(|c| match c {
             '-' | '_' => None,
+            x if preserve_case => Some(x),
             x => Some(x.to_ascii_lowercase()),
         })
         .collect::<String>();
     if prefix.is_empty() {
         return suffix;
     }
-    format!("{prefix}.{suffix}",)
+    format!("{prefix}{separator}{suffix}",)
 }
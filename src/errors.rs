@@ -1,20 +1,111 @@
-use std::{
-    ffi::OsStr,
-    io::{stderr, Write},
-};
+use std::{ffi::OsStr, fmt, io, io::Write};
 
-use cssparser::{BasicParseErrorKind, SourceLocation};
+use cssparser::{BasicParseErrorKind, SourceLocation, ToCss};
 
 use crate::parse;
 
+/// An error that ends the program.
+///
+/// Each kind has its own exit code, so scripts can tell them apart (clap uses
+/// 2 for invalid arguments).
+#[derive(Debug)]
+pub enum CliError {
+    /// A style-sheet (or c2theme) couldn't be parsed.
+    Parse(anyhow::Error),
+    /// The values of a style-sheet couldn't be resolved.
+    Resolve(anyhow::Error),
+    /// A file couldn't be read or written.
+    Io(anyhow::Error),
+    /// A layout couldn't be loaded.
+    Layout(anyhow::Error),
+    Other(anyhow::Error),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Other(_) => 1,
+            CliError::Parse(_) => 3,
+            CliError::Resolve(_) => 4,
+            CliError::Io(_) => 5,
+            CliError::Layout(_) => 6,
+        }
+    }
+
+    /// Replaces the error, keeping its kind (and thus its exit code).
+    pub fn replace(self, error: anyhow::Error) -> Self {
+        match self {
+            CliError::Parse(_) => CliError::Parse(error),
+            CliError::Resolve(_) => CliError::Resolve(error),
+            CliError::Io(_) => CliError::Io(error),
+            CliError::Layout(_) => CliError::Layout(error),
+            CliError::Other(_) => CliError::Other(error),
+        }
+    }
+
+    pub fn error(&self) -> &anyhow::Error {
+        match self {
+            CliError::Parse(e)
+            | CliError::Resolve(e)
+            | CliError::Io(e)
+            | CliError::Layout(e)
+            | CliError::Other(e) => e,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.error(), f)
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(e: io::Error) -> Self {
+        CliError::Io(e.into())
+    }
+}
+
+impl From<anyhow::Error> for CliError {
+    fn from(e: anyhow::Error) -> Self {
+        CliError::Other(e)
+    }
+}
+
+/// Prints `message` below the line at `location` to `out`.
+///
+/// If `end_column` is known, the span from `location.column` to `end_column`
+/// (exclusive) is underlined, otherwise a single position is marked.
 pub fn print_error_with_source(
+    out: &mut impl Write,
     source_id: &OsStr,
     source: &str,
     message: &str,
     location: &SourceLocation,
+    end_column: Option<u32>,
 ) {
-    if !try_print_error_with_source(source_id, source, message, location) {
-        print_message_and_loc(source_id, message, location);
+    if !try_print_error_with_source(
+        out, source_id, source, message, location, end_column,
+    ) {
+        print_message_and_loc(out, source_id, message, location);
+    }
+}
+
+/// Returns the (exclusive) end column of the token that caused `e`, if known.
+pub fn css_error_end_column(
+    e: &cssparser::ParseError<parse::ParseError>,
+) -> Option<u32> {
+    match &e.kind {
+        cssparser::ParseErrorKind::Basic(
+            BasicParseErrorKind::UnexpectedToken(t),
+        ) => {
+            let len = t.to_css_string().chars().count() as u32;
+            Some(e.location.column + len)
+        }
+        cssparser::ParseErrorKind::Custom(
+            parse::ParseError::InvalidHexColor(len),
+        ) => Some(e.location.column + 1 + *len as u32),
+        _ => None,
     }
 }
 
@@ -42,60 +133,151 @@ pub fn format_css_parse_error(
 }
 
 fn try_print_error_with_source(
+    out: &mut impl Write,
     source_id: &OsStr,
     source: &str,
     message: &str,
     location: &SourceLocation,
+    end_column: Option<u32>,
 ) -> bool {
-    let Some(prev_line) = source.bytes().enumerate().filter(|&(_, x)| x == b'\n').map(|(i,_)| i).nth(location.line.saturating_sub(2) as usize) else {
-            return false;
-        };
-    if source.len() - prev_line < 3 {
-        return false;
-    }
-    let start = &source[prev_line + 1..];
-    let (Some(prev_line_end), Some(err_line_end)) = ({
-        let mut it = start.bytes().enumerate().filter(|&(_, x)| x == b'\n').map(|(i,_)| i);
-        let first = it.next();
-        let second = it.next();
-        (first, second)
-    }) else {
+    // cssparser's lines are zero-based, we display them one-based
+    let line = location.line as usize;
+    // `lines()` strips a trailing '\r' (CRLF line endings)
+    let mut lines = source.lines().skip(line.saturating_sub(1));
+    // errors on the first line don't have a previous line
+    let prev_line = if line > 0 { lines.next() } else { None };
+    let Some(current_line) = lines.next() else {
         return false;
     };
 
-    let err_line_end = fix_clrf(start, err_line_end);
-    let current_line = &start[prev_line_end + 1..err_line_end];
-    let prev_line_end = fix_clrf(start, prev_line_end);
-
-    eprintln!("{}:", source_id.to_string_lossy());
-    eprintln!("{:>5}│ {}", location.line - 1, &start[..prev_line_end]);
-    eprintln!("{:>5}│ {}", location.line, current_line);
-    let mut stderr = stderr().lock();
-    for _ in 0..(5 + 2 + location.column - 1) {
-        stderr.write_all(&[b' ']).ok();
+    writeln!(out, "{}:", source_id.to_string_lossy()).ok();
+    if let Some(prev_line) = prev_line {
+        writeln!(out, "{:>5}│ {}", line, prev_line).ok();
+    }
+    writeln!(out, "{:>5}│ {}", line + 1, current_line).ok();
+    // mirror tabs in the padding, so the marker lines up with the source
+    let mut padding = " ".repeat(5 + 2);
+    padding.extend(
+        current_line
+            .chars()
+            .chain(std::iter::repeat(' '))
+            .take((location.column - 1) as usize)
+            .map(|c| if c == '\t' { '\t' } else { ' ' }),
+    );
+    match end_column {
+        Some(end) if end > location.column + 1 => {
+            let underline = "~".repeat((end - location.column) as usize);
+            writeln!(out, "{padding}{underline}").ok();
+        }
+        _ => (),
     }
-    writeln!(stderr, "╰─► {message}").ok();
+    writeln!(out, "{padding}╰─► {message}").ok();
 
     true
 }
 
 fn print_message_and_loc(
+    out: &mut impl Write,
     source_id: &OsStr,
     message: &str,
     location: &SourceLocation,
 ) {
-    eprintln!(
+    writeln!(
+        out,
         "[{} @ line {}, column {}] {message}",
         source_id.to_string_lossy(),
-        location.line,
+        location.line + 1,
         location.column
-    );
+    )
+    .ok();
 }
 
-fn fix_clrf(source: &str, pos: usize) -> usize {
-    if pos > 1 && source.as_bytes()[pos - 2] == b'\r' {
-        pos - 1
-    } else {
-        pos
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn print(source: &str, line: u32, column: u32, end: Option<u32>) -> String {
+        let mut out = Vec::new();
+        print_error_with_source(
+            &mut out,
+            OsStr::new("Theme.css"),
+            source,
+            "message",
+            &SourceLocation { line, column },
+            end,
+        );
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn error_on_first_line() {
+        let expected = "\
+Theme.css:
+    1│ a { b: #ff; }
+              ~~~
+              ╰─► message
+";
+        assert_eq!(
+            print("a { b: #ff; }\nc { d: red; }", 0, 8, Some(11)),
+            expected
+        );
+        // CRLF line endings and a single line without a newline
+        assert_eq!(
+            print("a { b: #ff; }\r\nc { d: red; }", 0, 8, Some(11)),
+            expected
+        );
+        assert_eq!(print("a { b: #ff; }", 0, 8, Some(11)), expected);
+    }
+
+    #[test]
+    fn parse_error_on_first_line() {
+        let source =
+            "a { b: #ff; }\n@chatterino { author: \"a\"; icon-set: \"dark\"; }";
+        let mut input = cssparser::ParserInput::new(source);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let (_, errors) = parse::parse_recovering(
+            &mut parser,
+            None,
+            parse::ParseOptions::STRICT,
+        );
+        let [e] = &errors[..] else {
+            panic!("expected one error, got {errors:?}");
+        };
+        assert_eq!(
+            print(
+                source,
+                e.location.line,
+                e.location.column,
+                css_error_end_column(e)
+            ),
+            print("a { b: #ff; }", 0, 8, Some(11)),
+        );
+    }
+
+    #[test]
+    fn error_with_previous_line() {
+        let expected = "\
+Theme.css:
+    1│ a { b: #ff; }
+    2│ c { d: rde; }
+              ~~~
+              ╰─► message
+";
+        assert_eq!(
+            print("a { b: #ff; }\nc { d: rde; }\n", 1, 8, Some(11)),
+            expected
+        );
+        assert_eq!(
+            print("a { b: #ff; }\r\nc { d: rde; }\r\n", 1, 8, Some(11)),
+            expected
+        );
+    }
+
+    #[test]
+    fn error_outside_of_the_source() {
+        assert_eq!(
+            print("a { b: #ff; }", 3, 2, None),
+            "[Theme.css @ line 4, column 2] message\n"
+        );
     }
 }
@@ -0,0 +1,62 @@
+use std::{fs, path::Path};
+
+const LAYOUT: &str = include_str!("templates/layout.yml");
+const STYLE: &str = include_str!("templates/Dark.css");
+
+/// Writes a starter `layout.yml` and `Dark.css` to `dir`.
+pub fn init(dir: &Path) -> anyhow::Result<()> {
+    let files = [
+        (dir.join("layout.yml"), LAYOUT),
+        (dir.join("Dark.css"), STYLE),
+    ];
+    if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+        anyhow::bail!("{} already exists", path.display());
+    }
+
+    fs::create_dir_all(dir)?;
+    for (path, content) in files {
+        fs::write(&path, content)?;
+        println!("Created {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cssparser::ParserInput;
+
+    use super::*;
+    use crate::{
+        combinator::DEFAULT_SEPARATOR, errors, layout::Layout, parse,
+        printer::r#impl::find_missing_rules,
+    };
+
+    /// Makes sure the templates can be used to generate code.
+    #[test]
+    fn templates_are_valid() {
+        let layout = Layout::parse(LAYOUT)
+            .unwrap_or_else(|e| panic!("Invalid layout template: {e}"));
+        let flat_layout = layout
+            .flatten(false)
+            .unwrap_or_else(|e| panic!("Invalid layout template: {e}"));
+
+        let mut input = ParserInput::new(STYLE);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let theme =
+            parse::parse(&mut parser, None, parse::ParseOptions::STRICT)
+                .unwrap_or_else(|e| {
+                    let message = errors::format_css_parse_error(&e);
+                    panic!("Invalid style template: {message}")
+                });
+        let (flat, _) = theme
+            .flatten(DEFAULT_SEPARATOR, false, None)
+            .unwrap_or_else(|e| panic!("Invalid style template: {e}"));
+
+        let missing = find_missing_rules(&flat_layout, &flat, false);
+        assert!(
+            missing.is_empty(),
+            "Style template doesn't define: {}",
+            missing.join(", ")
+        );
+    }
+}
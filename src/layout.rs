@@ -1,15 +1,124 @@
-use std::collections::BTreeMap;
+use std::{borrow::Cow, collections::BTreeMap};
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
+use cssparser::{Color, Parser, ParserInput, RGBA};
 use serde::Deserialize;
 
+use crate::combinator::{combine_path, DEFAULT_SEPARATOR};
+
+/// Keywords (and alternative tokens) of C++20, which can't be used as the
+/// names of structs or members.
+const CPP_KEYWORDS: &[&str] = &[
+    "alignas",
+    "alignof",
+    "and",
+    "and_eq",
+    "asm",
+    "auto",
+    "bitand",
+    "bitor",
+    "bool",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "char8_t",
+    "char16_t",
+    "char32_t",
+    "class",
+    "compl",
+    "concept",
+    "const",
+    "consteval",
+    "constexpr",
+    "constinit",
+    "const_cast",
+    "continue",
+    "co_await",
+    "co_return",
+    "co_yield",
+    "decltype",
+    "default",
+    "delete",
+    "do",
+    "double",
+    "dynamic_cast",
+    "else",
+    "enum",
+    "explicit",
+    "export",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "friend",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "mutable",
+    "namespace",
+    "new",
+    "noexcept",
+    "not",
+    "not_eq",
+    "nullptr",
+    "operator",
+    "or",
+    "or_eq",
+    "private",
+    "protected",
+    "public",
+    "register",
+    "reinterpret_cast",
+    "requires",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "static_assert",
+    "static_cast",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "thread_local",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "typeid",
+    "typename",
+    "union",
+    "unsigned",
+    "using",
+    "virtual",
+    "void",
+    "volatile",
+    "wchar_t",
+    "while",
+    "xor",
+    "xor_eq",
+];
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 enum YamlFields<'a> {
     #[serde(borrow)]
     Nested(BTreeMap<&'a str, Option<YamlStruct<'a>>>),
     #[serde(borrow)]
-    Sequence(Vec<&'a str>),
+    Sequence(Vec<YamlSequenceItem<'a>>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum YamlSequenceItem<'a> {
+    #[serde(borrow)]
+    Field(&'a str),
+    #[serde(borrow)]
+    Nested(BTreeMap<&'a str, Option<YamlStruct<'a>>>),
 }
 
 #[derive(Debug, Deserialize)]
@@ -17,16 +126,25 @@ struct YamlStruct<'a> {
     fields: Option<YamlFields<'a>>,
     #[serde(borrow)]
     r#ref: Option<&'a str>,
+    #[serde(borrow)]
+    default: Option<&'a str>,
+    /// Expands the field to `count` fields suffixed with their index.
+    count: Option<usize>,
+    /// Documentation of a definition or a top level struct (block scalars
+    /// can't be borrowed).
+    doc: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct YamlRootFile<'a> {
-    #[serde(borrow)]
+    // both are optional, so they can be split into multiple files
+    #[serde(borrow, default)]
     definitions: AHashMap<&'a str, YamlStruct<'a>>,
-    #[serde(borrow)]
+    #[serde(borrow, default)]
     layout: AHashMap<&'a str, YamlStruct<'a>>,
 }
 
+#[derive(Clone)]
 pub enum LayoutItem<'a> {
     Ref {
         field_name: &'a str,
@@ -34,7 +152,9 @@ pub enum LayoutItem<'a> {
         item_count: usize,
     },
     Field {
-        name: &'a str,
+        /// Owned for the fields generated by `count`.
+        name: Cow<'a, str>,
+        default: Option<RGBA>,
     },
     Struct {
         field_name: &'a str,
@@ -44,6 +164,14 @@ pub enum LayoutItem<'a> {
 }
 
 impl<'a> LayoutItem<'a> {
+    pub fn name(&self) -> &str {
+        match self {
+            LayoutItem::Ref { field_name, .. } => field_name,
+            LayoutItem::Field { name, .. } => name,
+            LayoutItem::Struct { field_name, .. } => field_name,
+        }
+    }
+
     pub fn item_count(&self) -> usize {
         match self {
             LayoutItem::Ref { item_count, .. } => *item_count,
@@ -53,22 +181,25 @@ impl<'a> LayoutItem<'a> {
     }
 }
 
+/// A definition or a top level struct.
 pub struct LayoutDefinition<'a> {
     pub fields: Vec<LayoutItem<'a>>,
     pub item_count: usize,
+    pub doc: Option<String>,
 }
 
 // we're using a BTreeMap here to keep the ouput sorted
 // (avoids recompilations at the cost of speed)
 pub struct Layout<'a> {
     pub definitions: BTreeMap<&'a str, LayoutDefinition<'a>>,
-    pub items: BTreeMap<&'a str, Vec<LayoutItem<'a>>>,
+    pub items: BTreeMap<&'a str, LayoutDefinition<'a>>,
 }
 
 pub enum FlatLayoutItem<'a> {
     Field {
-        name: &'a str,
+        name: Cow<'a, str>,
         id: usize,
+        default: Option<RGBA>,
     },
     Struct {
         name: &'a str,
@@ -76,66 +207,165 @@ pub enum FlatLayoutItem<'a> {
     },
 }
 
+impl<'a> FlatLayoutItem<'a> {
+    pub fn name(&self) -> &str {
+        match self {
+            FlatLayoutItem::Field { name, .. } => name,
+            FlatLayoutItem::Struct { name, .. } => name,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ParseError<'a> {
     #[error("Deserialization error: {0}")]
     Serde(#[from] serde_yaml::Error),
     #[error("Couldn't find definition for '{0}'")]
     RefNotFound(&'a str),
-    #[error("Found struct with both 'ref' and 'fields' in {0}")]
-    RefAndFields(&'a str),
+    #[error("Found struct with neither 'ref' nor 'fields' in {0}")]
+    MissingRefOrFields(&'a str),
     #[error("Definition of {0} isn't a struct")]
     DefinitionNotStruct(&'a str),
     #[error("Layout of {0} isn't a struct")]
     LayoutNotStruct(&'a str),
+    #[error("Found duplicate field '{0}' in {1}")]
+    DuplicateField(String, &'a str),
+    #[error("Found struct with a 'default' in {0}")]
+    DefaultOnStruct(&'a str),
+    #[error("Found struct with a 'count' in {0}")]
+    CountOnStruct(&'a str),
+    #[error("'count' of {0} must be at least 1")]
+    ZeroCount(&'a str),
+    #[error("Found 'doc' in {0} (only definitions and top level structs can be documented)")]
+    MisplacedDoc(&'a str),
+    #[error("Default of {0} isn't a color ({1})")]
+    InvalidDefault(&'a str, &'a str),
+    #[error("'{0}' isn't a top level item of the layout")]
+    UnknownItem(String),
+    #[error("'{0}' is defined in multiple files")]
+    DuplicateDefinition(&'a str),
+    #[error("'{0}' is in the layout of multiple files")]
+    DuplicateLayout(&'a str),
+    #[error("'{0}' (in {1}) is a C++ keyword, so it can't be used as a name")]
+    ReservedName(String, &'a str),
+    #[error("'{0}' (in {1}) sets the alpha of a block in style-sheets, so it can't be used as the name of a color")]
+    ReservedColorName(String, &'a str),
+    #[error("Fields {0} and {1} have the same key ({2})")]
+    CollidingFields(String, String, String),
+    #[error("Internal error: the layout has {0} items, but {1} fields were assigned ids")]
+    InconsistentItemCount(usize, usize),
+    #[error("Internal error: field {0} has the id {1}, expected {2}")]
+    NonContiguousId(String, usize, usize),
+    #[error("Internal error: {0} is declared as {1}, but initialized as {2}")]
+    MemberOrder(String, String, String),
 }
 
 impl<'a> Layout<'a> {
+    #[cfg(test)]
     pub fn parse(source: &'a str) -> Result<Self, ParseError<'a>> {
-        let yaml: YamlRootFile = serde_yaml::from_str(source)?;
+        Self::parse_many(&[source])
+    }
+
+    /// Parses multiple layout files as if they were a single one.
+    ///
+    /// A definition or a top level item must only be in one of the files.
+    pub fn parse_many(sources: &[&'a str]) -> Result<Self, ParseError<'a>> {
+        let mut yaml = YamlRootFile {
+            definitions: Default::default(),
+            layout: Default::default(),
+        };
+        for source in sources {
+            let file: YamlRootFile = serde_yaml::from_str(source)?;
+            for (key, value) in file.definitions {
+                if yaml.definitions.insert(key, value).is_some() {
+                    return Err(ParseError::DuplicateDefinition(key));
+                }
+            }
+            for (key, value) in file.layout {
+                if yaml.layout.insert(key, value).is_some() {
+                    return Err(ParseError::DuplicateLayout(key));
+                }
+            }
+        }
 
         let mut layout = Self {
             definitions: Default::default(),
             items: Default::default(),
         };
 
+        for key in yaml.definitions.keys() {
+            check_name(key, "definitions")?;
+        }
+        for key in yaml.layout.keys() {
+            check_name(key, "layout")?;
+        }
+
         for (key, value) in yaml.definitions {
             let LayoutItem::Struct {fields, item_count, ..} =
                 convert_struct(&layout, key, &value)? else {
                     return Err(ParseError::DefinitionNotStruct(key));
                 };
 
-            layout
-                .definitions
-                .insert(key, LayoutDefinition { fields, item_count });
+            layout.definitions.insert(
+                key,
+                LayoutDefinition {
+                    fields,
+                    item_count,
+                    doc: value.doc,
+                },
+            );
         }
 
         for (key, value) in yaml.layout {
-            let LayoutItem::Struct {fields, ..} =
-                convert_struct(&layout, key, &value)? else {
-                    return Err(ParseError::LayoutNotStruct(key));
-                };
+            let LayoutItem::Struct {
+                fields, item_count, ..
+            } = convert_struct(&layout, key, &value)?
+            else {
+                return Err(ParseError::LayoutNotStruct(key));
+            };
 
-            layout.items.insert(key, fields);
+            layout.items.insert(
+                key,
+                LayoutDefinition {
+                    fields,
+                    item_count,
+                    doc: value.doc,
+                },
+            );
         }
 
         Ok(layout)
     }
 
+    /// Removes all top level items that aren't in `names`.
+    pub fn retain_items(
+        &mut self,
+        names: &[String],
+    ) -> Result<(), ParseError<'a>> {
+        if let Some(unknown) =
+            names.iter().find(|n| !self.items.contains_key(n.as_str()))
+        {
+            return Err(ParseError::UnknownItem(unknown.clone()));
+        }
+        self.items.retain(|name, _| names.iter().any(|n| n == name));
+        Ok(())
+    }
+
     pub fn count_items(&self) -> usize {
-        self.items
-            .values()
-            .flat_map(|s| s.iter().map(|i| i.item_count()))
-            .sum()
+        self.items.values().map(|s| s.item_count).sum()
     }
 
-    pub fn flatten(&self) -> Vec<FlatLayoutItem<'a>> {
+    /// Keys are lowercased unless `preserve_case` is set.
+    pub fn flatten(
+        &self,
+        preserve_case: bool,
+    ) -> Result<Vec<FlatLayoutItem<'a>>, ParseError<'a>> {
         fn convert_items<'a>(
             item_id: &mut usize,
             layout: &Layout<'a>,
             name: &'a str,
             items: &[LayoutItem<'a>],
-        ) -> FlatLayoutItem<'a> {
+        ) -> Result<FlatLayoutItem<'a>, ParseError<'a>> {
             let mut converted = vec![];
             for item in items {
                 match item {
@@ -144,19 +374,24 @@ impl<'a> Layout<'a> {
                         referenced,
                         ..
                     } => {
-                        let Some(referenced) = layout.definitions.get(referenced) else {
-                            panic!("referenced struct not found ({referenced})");
+                        let Some(definition) =
+                            layout.definitions.get(referenced)
+                        else {
+                            return Err(ParseError::RefNotFound(referenced));
                         };
                         converted.push(convert_items(
                             item_id,
                             layout,
                             field_name,
-                            &referenced.fields,
-                        ))
+                            &definition.fields,
+                        )?)
                     }
-                    LayoutItem::Field { name } => {
-                        converted
-                            .push(FlatLayoutItem::Field { name, id: *item_id });
+                    LayoutItem::Field { name, default } => {
+                        converted.push(FlatLayoutItem::Field {
+                            name: name.clone(),
+                            id: *item_id,
+                            default: *default,
+                        });
                         *item_id += 1;
                     }
                     LayoutItem::Struct {
@@ -164,31 +399,233 @@ impl<'a> Layout<'a> {
                     } => {
                         converted.push(convert_items(
                             item_id, layout, field_name, fields,
-                        ));
+                        )?);
                     }
                 }
             }
-            FlatLayoutItem::Struct {
+            Ok(FlatLayoutItem::Struct {
                 name,
                 fields: converted,
+            })
+        }
+
+        /// Checks that the ids are `0..n` in order.
+        fn check_ids<'a>(
+            next_id: &mut usize,
+            item: &FlatLayoutItem<'a>,
+        ) -> Result<(), ParseError<'a>> {
+            match item {
+                FlatLayoutItem::Field { name, id, .. } => {
+                    if *id != *next_id {
+                        return Err(ParseError::NonContiguousId(
+                            name.to_string(),
+                            *id,
+                            *next_id,
+                        ));
+                    }
+                    *next_id += 1;
+                }
+                FlatLayoutItem::Struct { fields, .. } => {
+                    for field in fields {
+                        check_ids(next_id, field)?;
+                    }
+                }
             }
+            Ok(())
+        }
+
+        /// Checks that no two fields end up with the same key (e.g.
+        /// `foo-bar` and `fooBar`).
+        fn check_keys<'a>(
+            keys: &mut AHashMap<String, String>,
+            key_prefix: &str,
+            path_prefix: &str,
+            item: &FlatLayoutItem<'a>,
+            preserve_case: bool,
+        ) -> Result<(), ParseError<'a>> {
+            let name = item.name();
+            let key = combine_path(
+                key_prefix,
+                name,
+                DEFAULT_SEPARATOR,
+                preserve_case,
+            );
+            let path = match path_prefix {
+                "" => name.to_string(),
+                _ => format!("{path_prefix}.{name}"),
+            };
+            match item {
+                FlatLayoutItem::Field { .. } => {
+                    if let Some(other) = keys.insert(key.clone(), path.clone())
+                    {
+                        return Err(ParseError::CollidingFields(
+                            other, path, key,
+                        ));
+                    }
+                }
+                FlatLayoutItem::Struct { fields, .. } => {
+                    for field in fields {
+                        check_keys(keys, &key, &path, field, preserve_case)?;
+                    }
+                }
+            }
+            Ok(())
         }
 
         let mut item_id = 0;
         let mut items = vec![];
         for (name, s) in self.items.iter() {
-            items.push(convert_items(&mut item_id, self, name, s));
+            items.push(convert_items(&mut item_id, self, name, &s.fields)?);
+        }
+
+        // the generated array is sized with `count_items()` and indexed with
+        // the ids, so these have to agree
+        let mut n_fields = 0;
+        for item in &items {
+            check_ids(&mut n_fields, item)?;
+        }
+        if n_fields != self.count_items() {
+            return Err(ParseError::InconsistentItemCount(
+                self.count_items(),
+                n_fields,
+            ));
+        }
+
+        // the structs are initialized positionally, so the members have to
+        // be in the order they're declared in the header
+        for ((name, s), item) in self.items.iter().zip(&items) {
+            let FlatLayoutItem::Struct { fields, .. } = item else {
+                return Err(ParseError::LayoutNotStruct(name));
+            };
+            check_order(self, name, &s.fields, fields)?;
+        }
+
+        let mut keys = AHashMap::new();
+        for item in &items {
+            check_keys(&mut keys, "", "", item, preserve_case)?;
         }
 
-        items
+        Ok(items)
     }
 }
 
+/// Checks that `flat` has the same members in the same order as `declared`
+/// (the fields of the struct `name`), following references like the header
+/// does.
+fn check_order<'a>(
+    layout: &Layout<'a>,
+    name: &str,
+    declared: &[LayoutItem<'a>],
+    flat: &[FlatLayoutItem<'a>],
+) -> Result<(), ParseError<'a>> {
+    let names = |items: Vec<&str>| items.join(", ");
+    let mismatch = || {
+        ParseError::MemberOrder(
+            name.to_owned(),
+            names(declared.iter().map(|item| item.name()).collect()),
+            names(flat.iter().map(|item| item.name()).collect()),
+        )
+    };
+    if declared.len() != flat.len() {
+        return Err(mismatch());
+    }
+    for (declared, flat) in declared.iter().zip(flat) {
+        if declared.name() != flat.name() {
+            return Err(mismatch());
+        }
+        let fields = match declared {
+            LayoutItem::Field { .. } => continue,
+            LayoutItem::Struct { fields, .. } => fields,
+            LayoutItem::Ref { referenced, .. } => {
+                match layout.definitions.get(referenced) {
+                    Some(definition) => &definition.fields,
+                    None => return Err(ParseError::RefNotFound(referenced)),
+                }
+            }
+        };
+        let FlatLayoutItem::Struct { fields: flat, .. } = flat else {
+            return Err(mismatch());
+        };
+        check_order(layout, declared.name(), fields, flat)?;
+    }
+    Ok(())
+}
+
+/// Returns the key (`tabs.regular.text` with a `.` separator) and id of
+/// every field, ordered by the id.
+pub fn keys(
+    flat_layout: &[FlatLayoutItem],
+    separator: &str,
+    preserve_case: bool,
+) -> Vec<(String, usize)> {
+    fn collect(
+        keys: &mut Vec<(String, usize)>,
+        prefix: &str,
+        item: &FlatLayoutItem,
+        separator: &str,
+        preserve_case: bool,
+    ) {
+        match item {
+            FlatLayoutItem::Field { name, id, .. } => keys.push((
+                combine_path(prefix, name, separator, preserve_case),
+                *id,
+            )),
+            FlatLayoutItem::Struct { name, fields } => {
+                let prefix =
+                    combine_path(prefix, name, separator, preserve_case);
+                for field in fields {
+                    collect(keys, &prefix, field, separator, preserve_case);
+                }
+            }
+        }
+    }
+
+    let mut keys = vec![];
+    for item in flat_layout {
+        collect(&mut keys, "", item, separator, preserve_case);
+    }
+    keys
+}
+
+/// Returns the size of an array indexed by the ids of `flat_layout`.
+///
+/// This is more than the number of fields if only some items of a layout
+/// were flattened, but they kept their ids.
+pub fn id_count(flat_layout: &[FlatLayoutItem]) -> usize {
+    fn max_id(item: &FlatLayoutItem) -> Option<usize> {
+        match item {
+            FlatLayoutItem::Field { id, .. } => Some(*id),
+            FlatLayoutItem::Struct { fields, .. } => {
+                fields.iter().filter_map(max_id).max()
+            }
+        }
+    }
+
+    flat_layout
+        .iter()
+        .filter_map(max_id)
+        .max()
+        .map_or(0, |id| id + 1)
+}
+
 fn convert_struct<'a>(
     current: &Layout<'a>,
     name: &'a str,
     s: &YamlStruct<'a>,
 ) -> Result<LayoutItem<'a>, ParseError<'a>> {
+    if s.count.is_some() {
+        return Err(ParseError::CountOnStruct(name));
+    }
+    if let Some(default) = s.default {
+        if s.r#ref.is_some() || s.fields.is_some() {
+            return Err(ParseError::DefaultOnStruct(name));
+        }
+        return Ok(LayoutItem::Field {
+            name: name.into(),
+            default: Some(parse_default(name, default)?),
+        });
+    }
+
     match (&s.r#ref, &s.fields) {
         (Some(r), None) => {
             let Some(d) = current.definitions.get(r) else {
@@ -200,33 +637,38 @@ fn convert_struct<'a>(
                 referenced: r,
             })
         }
-        (None, Some(fields)) => {
-            let mut items = Vec::new();
-            let mut item_count = 0;
-            match fields {
-                YamlFields::Nested(n) => {
-                    for (name, inner) in n {
-                        match inner {
-                            Some(ref inner) => {
-                                let converted =
-                                    convert_struct(current, name, inner)?;
-                                item_count += converted.item_count();
-                                items.push(converted);
-                            }
-                            None => {
-                                items.push(LayoutItem::Field { name });
-                                item_count += 1;
-                            }
-                        }
-                    }
+        (r, Some(fields)) => {
+            let own = convert_fields(current, fields)?;
+            for item in &own {
+                check_name(item.name(), name)?;
+                if let LayoutItem::Field { name: field, .. } = item {
+                    check_color_name(field, name)?;
                 }
-                YamlFields::Sequence(s) => {
-                    for name in s {
-                        items.push(LayoutItem::Field { name });
-                    }
-                    item_count += s.len();
+            }
+            let mut names = AHashSet::new();
+            if let Some(dup) = own.iter().find(|i| !names.insert(i.name())) {
+                return Err(ParseError::DuplicateField(
+                    dup.name().to_owned(),
+                    name,
+                ));
+            }
+
+            // fields of the referenced definition are overridden by (or
+            // extended with) the fields specified here
+            let mut items = match r {
+                Some(r) => match current.definitions.get(r) {
+                    Some(d) => d.fields.clone(),
+                    None => return Err(ParseError::RefNotFound(r)),
+                },
+                None => Vec::new(),
+            };
+            for item in own {
+                match items.iter_mut().find(|i| i.name() == item.name()) {
+                    Some(existing) => *existing = item,
+                    None => items.push(item),
                 }
             }
+            let item_count = items.iter().map(|i| i.item_count()).sum();
 
             Ok(LayoutItem::Struct {
                 field_name: name,
@@ -234,6 +676,305 @@ fn convert_struct<'a>(
                 item_count,
             })
         }
-        _ => Err(ParseError::RefAndFields(name)),
+        (None, None) => Err(ParseError::MissingRefOrFields(name)),
+    }
+}
+
+fn convert_fields<'a>(
+    current: &Layout<'a>,
+    fields: &YamlFields<'a>,
+) -> Result<Vec<LayoutItem<'a>>, ParseError<'a>> {
+    let mut items = Vec::new();
+    match fields {
+        YamlFields::Nested(n) => {
+            for (name, inner) in n {
+                convert_field(current, name, inner, &mut items)?;
+            }
+        }
+        YamlFields::Sequence(s) => {
+            for entry in s {
+                match entry {
+                    YamlSequenceItem::Field(name) => {
+                        items.push(LayoutItem::Field {
+                            name: (*name).into(),
+                            default: None,
+                        });
+                    }
+                    YamlSequenceItem::Nested(n) => {
+                        for (name, inner) in n {
+                            convert_field(current, name, inner, &mut items)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(items)
+}
+
+fn convert_field<'a>(
+    current: &Layout<'a>,
+    name: &'a str,
+    inner: &Option<YamlStruct<'a>>,
+    items: &mut Vec<LayoutItem<'a>>,
+) -> Result<(), ParseError<'a>> {
+    match inner {
+        Some(YamlStruct {
+            count: Some(count),
+            r#ref,
+            fields,
+            default,
+            doc,
+        }) => {
+            if doc.is_some() {
+                return Err(ParseError::MisplacedDoc(name));
+            }
+            if r#ref.is_some() || fields.is_some() {
+                return Err(ParseError::CountOnStruct(name));
+            }
+            if *count == 0 {
+                return Err(ParseError::ZeroCount(name));
+            }
+            let default =
+                default.map(|d| parse_default(name, d)).transpose()?;
+            for i in 0..*count {
+                items.push(LayoutItem::Field {
+                    name: format!("{name}{i}").into(),
+                    default,
+                });
+            }
+        }
+        Some(inner) if inner.doc.is_some() => {
+            return Err(ParseError::MisplacedDoc(name))
+        }
+        Some(inner) => items.push(convert_struct(current, name, inner)?),
+        None => items.push(LayoutItem::Field {
+            name: name.into(),
+            default: None,
+        }),
+    }
+    Ok(())
+}
+
+/// Checks that `name` (in `parent`) can be used in the generated code.
+fn check_name<'a>(name: &str, parent: &'a str) -> Result<(), ParseError<'a>> {
+    match CPP_KEYWORDS.contains(&name) {
+        true => Err(ParseError::ReservedName(name.to_owned(), parent)),
+        false => Ok(()),
+    }
+}
+
+/// Declarations with these names (ignoring case) are parsed as the alpha of
+/// their block, so colors can't use them.
+const ALPHA_NAMES: &[&str] = &["alpha", "opacity"];
+
+fn check_color_name<'a>(
+    name: &str,
+    parent: &'a str,
+) -> Result<(), ParseError<'a>> {
+    match ALPHA_NAMES.iter().any(|a| a.eq_ignore_ascii_case(name)) {
+        true => Err(ParseError::ReservedColorName(name.to_owned(), parent)),
+        false => Ok(()),
+    }
+}
+
+fn parse_default<'a>(
+    name: &'a str,
+    value: &'a str,
+) -> Result<RGBA, ParseError<'a>> {
+    let mut input = ParserInput::new(value);
+    let mut parser = Parser::new(&mut input);
+    let color = parser.parse_entirely(|p| {
+        Color::parse(p).map_err(cssparser::ParseError::<()>::from)
+    });
+    match color {
+        Ok(Color::RGBA(color)) => Ok(color),
+        _ => Err(ParseError::InvalidDefault(name, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: &str = "
+definitions:
+  TabColors:
+    fields:
+      - text
+      - backgrounds: { fields: [regular, hover] }
+layout:
+  tabs:
+    fields:
+      - dividerLine
+      - regular: { ref: TabColors }
+  colors:
+    fields:
+      accent-color:
+      palette: { count: 2 }
+";
+
+    fn keys_of(source: &str, preserve_case: bool) -> Vec<(String, usize)> {
+        let layout = Layout::parse(source).unwrap();
+        let flat = layout.flatten(preserve_case).unwrap();
+        keys(&flat, DEFAULT_SEPARATOR, preserve_case)
+    }
+
+    #[test]
+    fn keys_are_ordered_by_id() {
+        let keys = keys_of(LAYOUT, false);
+        assert_eq!(
+            keys,
+            [
+                ("colors.accentcolor", 0),
+                ("colors.palette0", 1),
+                ("colors.palette1", 2),
+                ("tabs.dividerline", 3),
+                ("tabs.regular.text", 4),
+                ("tabs.regular.backgrounds.regular", 5),
+                ("tabs.regular.backgrounds.hover", 6),
+            ]
+            .map(|(key, id)| (key.to_owned(), id))
+        );
+    }
+
+    #[test]
+    fn keys_preserve_case() {
+        let keys = keys_of(LAYOUT, true);
+        // `-` and `_` are still removed
+        assert_eq!(keys[0].0, "colors.accentcolor");
+        assert_eq!(keys[3].0, "tabs.dividerLine");
+    }
+
+    #[test]
+    fn keys_are_unique() {
+        for preserve_case in [false, true] {
+            let keys = keys_of(LAYOUT, preserve_case);
+            let unique =
+                keys.iter().map(|(key, _)| key).collect::<AHashSet<_>>();
+            assert_eq!(unique.len(), keys.len());
+        }
+    }
+
+    #[test]
+    fn alpha_names_are_reserved_for_colors() {
+        for field in ["alpha", "Opacity"] {
+            let source =
+                format!("layout: {{ colors: {{ fields: [{field}] }} }}");
+            match Layout::parse(&source) {
+                Err(ParseError::ReservedColorName(name, "colors")) => {
+                    assert_eq!(name, field)
+                }
+                Err(e) => panic!("unexpected error: {e}"),
+                Ok(_) => panic!("'{field}' should be reserved"),
+            }
+        }
+        // structs are set with `@nest`, so they can use the names
+        Layout::parse(
+            "layout: { colors: { fields: [{ alpha: { fields: [text] } }] } }",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn colliding_keys_match_keys() {
+        // the key `check_keys` reports has to be the one `keys` generates
+        let single = "layout: { colors: { fields: [foo-bar] } }";
+        let [(key, _)] = &keys_of(single, false)[..] else {
+            panic!("expected a single key");
+        };
+
+        let both = "layout: { colors: { fields: [foo-bar, fooBar] } }";
+        let layout = Layout::parse(both).unwrap();
+        match layout.flatten(false) {
+            Err(ParseError::CollidingFields(_, _, collision)) => {
+                assert_eq!(&collision, key)
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("the fields should collide"),
+        }
+        // with the case preserved, they're different keys
+        assert_eq!(keys_of(both, true).len(), 2);
+    }
+
+    /// Checks the top level items of `flat` against `layout`, like `flatten`.
+    fn check_all<'a>(
+        layout: &Layout<'a>,
+        flat: &[FlatLayoutItem<'a>],
+    ) -> Result<(), ParseError<'a>> {
+        for ((name, s), item) in layout.items.iter().zip(flat) {
+            let FlatLayoutItem::Struct { fields, .. } = item else {
+                panic!("{name} should be a struct");
+            };
+            check_order(layout, name, &s.fields, fields)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the fields of the struct at `path` in `items`.
+    fn fields_at<'i, 'a>(
+        items: &'i mut [FlatLayoutItem<'a>],
+        path: &[&str],
+    ) -> &'i mut Vec<FlatLayoutItem<'a>> {
+        let (first, rest) = path.split_first().unwrap();
+        let item = items.iter_mut().find(|it| it.name() == *first).unwrap();
+        let FlatLayoutItem::Struct { fields, .. } = item else {
+            panic!("{first} should be a struct");
+        };
+        if rest.is_empty() {
+            fields
+        } else {
+            fields_at(fields, rest)
+        }
+    }
+
+    #[test]
+    fn member_order_matches() {
+        let layout = Layout::parse(LAYOUT).unwrap();
+        let flat = layout.flatten(false).unwrap();
+        check_all(&layout, &flat).unwrap();
+    }
+
+    #[test]
+    fn member_order_out_of_order_ids() {
+        let layout = Layout::parse(LAYOUT).unwrap();
+
+        // swapping two fields puts their ids out of order, so the positional
+        // initialization would assign the colors to the wrong members
+        let mut flat = layout.flatten(false).unwrap();
+        fields_at(&mut flat, &["colors"]).swap(0, 1);
+        match check_all(&layout, &flat) {
+            Err(ParseError::MemberOrder(name, declared, initialized)) => {
+                assert_eq!(name, "colors");
+                assert_eq!(declared, "accent-color, palette0, palette1");
+                assert_eq!(initialized, "palette0, accent-color, palette1");
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("the members should be out of order"),
+        }
+
+        // inside a referenced definition
+        let mut flat = layout.flatten(false).unwrap();
+        fields_at(&mut flat, &["tabs", "regular", "backgrounds"]).swap(0, 1);
+        match check_all(&layout, &flat) {
+            Err(ParseError::MemberOrder(name, declared, initialized)) => {
+                assert_eq!(name, "backgrounds");
+                assert_eq!(declared, "regular, hover");
+                assert_eq!(initialized, "hover, regular");
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+            Ok(_) => panic!("the members should be out of order"),
+        }
+    }
+
+    #[test]
+    fn member_order_missing_member() {
+        let layout = Layout::parse(LAYOUT).unwrap();
+        let mut flat = layout.flatten(false).unwrap();
+        fields_at(&mut flat, &["tabs", "regular"]).pop();
+        assert!(matches!(
+            check_all(&layout, &flat),
+            Err(ParseError::MemberOrder(name, _, _)) if name == "regular"
+        ));
     }
 }
@@ -1,7 +1,9 @@
 #![deny(clippy::cargo)]
 
+mod c2theme;
 mod combinator;
 mod errors;
+mod init;
 mod layout;
 mod model;
 mod parse;
@@ -10,12 +12,28 @@ mod printer;
 use std::{
     ffi::{OsStr, OsString},
     fs,
+    io::{self, Write},
     path::{Path, PathBuf},
+    process::ExitCode,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use clap::Parser;
-use cssparser::ParserInput;
-use printer::{header::generate_header, r#impl::generate_impl, Printer};
+use ahash::AHashMap;
+use anyhow::Context;
+use clap::{CommandFactory, Parser, ValueEnum};
+use combinator::DEFAULT_SEPARATOR;
+use cssparser::{Color, ParserInput, RGBA};
+use errors::CliError;
+use layout::{FlatLayoutItem, Layout};
+use model::{ColorScheme, FlatTheme, Theme};
+use parse::{ParseOptions, UnknownMeta};
+use printer::{
+    header::generate_header,
+    r#impl::{find_missing_rules, generate_impl},
+    ChannelOrder, CodeOptions, IndentStyle, Matcher, Printer,
+};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
@@ -23,132 +41,1269 @@ enum Args {
     /// Generate code to manage a theme.
     Code {
         #[clap(short, default_value = "layout.yml")]
-        /// Path to a layout.yml file that contains the theme layout.
-        layout: OsString,
+        /// Path to a layout.yml file that contains the theme layout (can be specified multiple times to merge files).
+        layout: Vec<OsString>,
+        #[clap(required_unless_present = "list_keys")]
         /// The default style that gets loaded when the theme is initially loaded (or when reset() is called).
-        default_style: OsString,
+        default_style: Option<OsString>,
+        #[clap(long, default_value_t = false)]
+        /// Resolve a relative DEFAULT_STYLE from the directory of the (first) layout file instead of the working directory.
+        style_relative_to_layout: bool,
         #[clap(short, default_value = ".")]
         /// Output directory for all generated files.
         output_dir: OsString,
+        #[clap(long, default_value = "GeneratedTheme")]
+        /// Base name of the generated files ('.cpp' and '.hpp' are appended) and name of the generated class.
+        name: OsString,
         #[clap(short, default_value_t = false)]
-        /// Whether to generate an additional 'GeneratedTheme.timestamp' file.
+        /// Whether to generate an additional '<NAME>.timestamp' file (contains the time and hashes of the generated files).
         timestamp: bool,
+        #[clap(long, default_value_t = false)]
+        /// Don't write anything, but fail if the generated files aren't up to date.
+        check: bool,
+        #[clap(long, default_value_t = false, conflicts_with_all = ["check", "timestamp"])]
+        /// Print the generated files to stdout (each after a '=== <path> ===' line) instead of writing them.
+        dry_run: bool,
+        #[clap(long, default_value_t = false)]
+        /// Only print the key and index of every color in the layout (no default style is needed).
+        list_keys: bool,
+        #[clap(long, value_parser = parse_color_arg, conflicts_with = "strict")]
+        /// Use this color for references to colors that don't exist (and print a warning) instead of failing (can't be combined with '--strict').
+        default_color: Option<RGBA>,
+        #[clap(long, default_value_t = false)]
+        /// Fail on unknown entries of '@chatterino' and on names declared twice in a block of the default style instead of printing a warning.
+        strict: bool,
+        #[clap(long, value_delimiter = ',')]
+        /// Only generate these top level items of the layout (e.g. 'tabs,splits'). The colors keep their indices from the full layout.
+        only: Vec<String>,
+        #[clap(long, default_value_t = false, requires = "only")]
+        /// With '--only', number the colors of the generated items from 0 instead of keeping their indices from the full layout.
+        renumber: bool,
+        #[clap(long, default_value = "tabs")]
+        /// Indentation of the generated code ('tabs' or a number of spaces).
+        indent: IndentStyle,
+        #[clap(long, default_value_t = false)]
+        /// Emit colors as 'QColor::fromRgb(r, g, b, a)' instead of '{r, g, b, a}'.
+        from_rgb: bool,
+        #[clap(long, default_value_t = false)]
+        /// Match the names passed to 'setColor' case-insensitively.
+        case_insensitive: bool,
+        #[clap(long, default_value_t = false)]
+        /// Make 'GeneratedTheme' a QObject with a Q_PROPERTY for every color (for QML).
+        qml: bool,
+        #[clap(long, default_value_t = false)]
+        /// Emit 'operator==' and 'operator!=' for 'GeneratedTheme' and the structs in 'definitions'.
+        eq_operators: bool,
+        #[clap(long, default_value_t = false)]
+        /// Emit 'GeneratedTheme::colorNames' that maps an index to its path (only if NDEBUG isn't defined).
+        color_names: bool,
+        #[clap(long, default_value_t = false)]
+        /// Emit 'GeneratedTheme::colorNames' in all builds (indexed by 'ThemeKey', matches the names accepted by 'setColor').
+        emit_keys: bool,
+        #[clap(long, default_value_t = false, conflicts_with = "from_rgb")]
+        /// Initialize the colors in 'reset' from a single constexpr array instead of one assignment per color.
+        color_array: bool,
+        #[clap(long, default_value_t = false)]
+        /// Emit 'loadTheme' that applies the colors of a c2theme (generated by 'theme') at runtime.
+        loader: bool,
+        #[clap(
+            long,
+            default_value_t = false,
+            conflicts_with = "case_insensitive"
+        )]
+        /// Keep the case of the names in the layout and style-sheet in keys (e.g. 'colors.accentColor' instead of 'colors.accentcolor').
+        preserve_case: bool,
+        #[clap(long, default_value_t = false)]
+        /// Emit 'getAllColors' that returns a map of every key to its current color (e.g. for a theme editor).
+        all_colors: bool,
+        #[clap(long, value_enum, default_value_t = Matcher::Map)]
+        /// How 'getDataIndex' looks up the names passed to 'setColor'.
+        matcher: Matcher,
+        #[clap(long = "include")]
+        /// Additional header to include in the implementation (e.g. 'precompiled.hpp' or '<QtCore>', can be specified multiple times).
+        includes: Vec<String>,
+        #[clap(long, default_value_t = false)]
+        /// Don't include the Qt and standard headers the implementation needs (e.g. when they come from '--include' or a precompiled header).
+        no_default_includes: bool,
+        #[clap(long, default_value_t = false)]
+        /// Initialize the structs in 'applyChanges' with designated initializers ('.text = ..', requires C++20).
+        designated_init: bool,
+        #[clap(long, default_value_t = false, conflicts_with = "color_array")]
+        /// Add a comment with the value from the default style to every color in 'reset' (e.g. '// from: rebeccapurple').
+        source_comments: bool,
+        #[clap(long, default_value_t = false)]
+        /// Print a summary of the generated files (number of colors, references, and lines) to stderr.
+        emit_stats: bool,
     },
-    /// Generates a 'c2theme' from a style-sheet.
+    /// Generates a 'c2theme' from one or more style-sheets.
     Theme {
-        /// Path to an input style-sheet, for example Dark.css.
-        input: OsString,
+        #[clap(required = true)]
+        /// Paths to (or glob patterns of) input style-sheets, for example Dark.css or 'themes/*.css'.
+        inputs: Vec<OsString>,
         #[clap(short, default_value = ".")]
         /// Output directory for all generated files.
         output_dir: OsString,
+        #[clap(long, conflicts_with = "output_dir")]
+        /// Path of the generated file (only possible with a single input).
+        out_file: Option<OsString>,
         #[clap(short, default_value_t = false)]
-        /// Whether to generate an additional .timestamp file.
+        /// Whether to generate an additional .timestamp file (contains the time and hash of the generated file).
         timestamp: bool,
+        #[clap(long, default_value_t = false, conflicts_with = "timestamp")]
+        /// Print the generated themes to stdout (each after a '=== <path> ===' line) instead of writing them.
+        dry_run: bool,
+        #[clap(long, value_enum, default_value_t = ThemeFormat::C2theme)]
+        /// Format of the generated theme.
+        format: ThemeFormat,
+        #[clap(long, default_value_t = false)]
+        /// Start a 'c2theme' with a comment naming the generator version and the input file.
+        header_comment: bool,
+        #[clap(long, default_value_t = false)]
+        /// Write colors as '#rrggbbaa' instead of '#aarrggbb' (Chatterino can't load these).
+        alpha_last: bool,
+        #[clap(long, default_value = DEFAULT_SEPARATOR, value_parser = parse_separator)]
+        /// Separator between the parts of a color's path (Chatterino expects '.').
+        separator: String,
+        #[clap(long, default_value_t = false)]
+        /// Keep the case of the names in the style-sheet in the paths of colors (e.g. 'colors.accentColor' instead of 'colors.accentcolor').
+        preserve_case: bool,
+        #[clap(long, default_value_t = false)]
+        /// Keep unknown entries of '@chatterino' (string values only) and write them to the theme as-is instead of ignoring them (with a warning).
+        meta_extras: bool,
+        #[clap(long, default_value_t = false)]
+        /// Fail on unknown entries of '@chatterino' (unless '--meta-extras' is set) and on names declared twice in a block instead of printing a warning.
+        strict: bool,
+        #[clap(long, value_parser = parse_color_arg, conflicts_with = "strict")]
+        /// Use this color for references to colors that don't exist (and print a warning) instead of failing (can't be combined with '--strict').
+        default_color: Option<RGBA>,
+        #[clap(long, default_value_t = false)]
+        /// Print a summary of every generated theme (number of colors and references) to stderr.
+        emit_stats: bool,
+    },
+    /// Prints the resolved colors that differ between two style-sheets.
+    Diff {
+        /// The old style-sheet.
+        old: OsString,
+        /// The new style-sheet.
+        new: OsString,
+    },
+    /// Converts a 'c2theme' back to a style-sheet.
+    Decompile {
+        /// Path to the 'c2theme'.
+        input: OsString,
+        #[clap(short)]
+        /// Path of the generated style-sheet [default: <INPUT> with a '.css' extension].
+        output: Option<OsString>,
+    },
+    /// Prints a completion script for a shell.
+    #[command(hide = true)]
+    Completions {
+        /// The shell to generate the script for.
+        shell: clap_complete::Shell,
     },
+    /// Creates a starter layout.yml and Dark.css.
+    Init {
+        #[clap(default_value = ".")]
+        /// Directory to create the files in.
+        dir: OsString,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ThemeFormat {
+    /// A 'c2theme' that can be loaded by Chatterino.
+    C2theme,
+    /// A JSON file with the metadata and the flattened colors.
+    Json,
+}
+
+impl ThemeFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ThemeFormat::C2theme => "c2theme",
+            ThemeFormat::Json => "json",
+        }
+    }
+}
+
+/// Where (and if) the `code` subcommand writes its files.
+struct CodeOutput<'a> {
+    dir: &'a OsStr,
+    /// Base name of the generated files.
+    name: &'a OsStr,
+    timestamp: bool,
+    /// Only compare the generated files with the existing ones.
+    check: bool,
+    /// Print the generated files instead of writing them.
+    dry_run: bool,
+    /// Print a summary of the generated files.
+    stats: bool,
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+/// The default style of the `code` subcommand and how it's loaded.
+struct DefaultStyle<'a> {
+    file: &'a OsStr,
+    /// Color for references to colors that don't exist.
+    fallback: Option<RGBA>,
+    parse: ParseOptions,
+}
 
+/// Options for the `theme` subcommand.
+#[derive(Debug, Clone, Copy)]
+struct ThemeOptions<'a> {
+    format: ThemeFormat,
+    timestamp: bool,
+    header_comment: bool,
+    order: ChannelOrder,
+    separator: &'a str,
+    preserve_case: bool,
+    parse: ParseOptions,
+    fallback: Option<RGBA>,
+    stats: bool,
+    /// Print the generated themes instead of writing them.
+    dry_run: bool,
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e.error());
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), CliError> {
     match args {
         Args::Code {
             layout,
             default_style,
+            style_relative_to_layout,
             output_dir,
+            name,
             timestamp,
-        } => generate_code(&layout, &default_style, &output_dir, timestamp),
+            check,
+            dry_run,
+            list_keys,
+            default_color,
+            strict,
+            only,
+            renumber,
+            indent,
+            from_rgb,
+            case_insensitive,
+            qml,
+            eq_operators,
+            color_names,
+            emit_keys,
+            color_array,
+            loader,
+            preserve_case,
+            all_colors,
+            matcher,
+            includes,
+            no_default_includes,
+            designated_init,
+            source_comments,
+            emit_stats,
+        } => match default_style {
+            Some(default_style) if !list_keys => generate_code(
+                &layout,
+                &Only {
+                    items: &only,
+                    renumber,
+                },
+                &DefaultStyle {
+                    file: &match layout.first() {
+                        Some(layout) if style_relative_to_layout => {
+                            relative_to(layout, &default_style)
+                        }
+                        _ => default_style,
+                    },
+                    fallback: default_color,
+                    parse: if strict {
+                        ParseOptions::STRICT
+                    } else {
+                        ParseOptions::default()
+                    },
+                },
+                &CodeOutput {
+                    dir: &output_dir,
+                    name: &name,
+                    timestamp,
+                    check,
+                    dry_run,
+                    stats: emit_stats,
+                },
+                indent,
+                &CodeOptions {
+                    class_name: class_name(&name)?,
+                    from_rgb,
+                    case_insensitive,
+                    qml,
+                    eq_operators,
+                    color_names,
+                    emit_keys,
+                    color_array,
+                    loader,
+                    preserve_case,
+                    all_colors,
+                    matcher,
+                    default_includes: !no_default_includes,
+                    includes,
+                    designated_init,
+                    source_comments,
+                },
+            ),
+            _ => list_layout_keys(
+                &layout,
+                &Only {
+                    items: &only,
+                    renumber,
+                },
+                preserve_case,
+            ),
+        },
         Args::Theme {
-            input,
+            inputs,
             output_dir,
+            out_file,
             timestamp,
-        } => generate_theme(&input, &output_dir, timestamp),
+            dry_run,
+            format,
+            header_comment,
+            alpha_last,
+            separator,
+            preserve_case,
+            meta_extras,
+            strict,
+            default_color,
+            emit_stats,
+        } => {
+            let options = ThemeOptions {
+                format,
+                timestamp,
+                header_comment,
+                order: if alpha_last {
+                    ChannelOrder::AlphaLast
+                } else {
+                    ChannelOrder::AlphaFirst
+                },
+                separator: &separator,
+                preserve_case,
+                parse: ParseOptions {
+                    unknown_meta: match (meta_extras, strict) {
+                        (true, _) => UnknownMeta::Keep,
+                        (_, true) => UnknownMeta::Error,
+                        _ => UnknownMeta::Ignore,
+                    },
+                    strict,
+                },
+                fallback: default_color,
+                stats: emit_stats,
+                dry_run,
+            };
+            let inputs = expand_inputs(&inputs)?;
+            if out_file.is_some() && inputs.len() > 1 {
+                return Err(CliError::Other(anyhow::anyhow!(
+                    "--out-file can only be used with a single input (got {})",
+                    inputs.len()
+                )));
+            }
+            let outputs = inputs
+                .iter()
+                .map(|input| {
+                    theme_output_path(
+                        input,
+                        &output_dir,
+                        out_file.as_deref(),
+                        options.format,
+                    )
+                })
+                .collect::<Vec<_>>();
+            // the themes are written in parallel, so two inputs with the
+            // same name (in different directories) would race
+            let mut written = AHashMap::new();
+            for (input, output) in inputs.iter().zip(&outputs) {
+                if let Some(other) = written.insert(output, input) {
+                    return Err(CliError::Other(anyhow::anyhow!(
+                        "{} and {} would both be written to {}",
+                        other.display(),
+                        input.display(),
+                        output.display()
+                    )));
+                }
+            }
+
+            // diagnostics are buffered per input to not interleave them
+            let results = inputs
+                .par_iter()
+                .zip(&outputs)
+                .map(|(input, output)| {
+                    let mut diagnostics = Vec::new();
+                    let res = generate_theme(
+                        &mut diagnostics,
+                        input.as_os_str(),
+                        output,
+                        &options,
+                    );
+                    (diagnostics, res)
+                })
+                .collect::<Vec<_>>();
+
+            // the first failure determines the exit code
+            let mut first_error = None;
+            let mut failed = 0;
+            let mut stderr = io::stderr().lock();
+            for (diagnostics, res) in results {
+                stderr.write_all(&diagnostics)?;
+                if let Err(e) = res {
+                    writeln!(stderr, "{e:#}")?;
+                    first_error.get_or_insert(e);
+                    failed += 1;
+                }
+            }
+            match first_error {
+                Some(e) => Err(e.replace(anyhow::anyhow!(
+                    "{failed} of {} themes couldn't be generated",
+                    inputs.len()
+                ))),
+                None => Ok(()),
+            }
+        }
+        Args::Diff { old, new } => {
+            let mut stderr = io::stderr();
+            let old_source = read_input(&old)?;
+            let new_source = read_input(&new)?;
+            let old = load_theme(
+                &mut stderr,
+                &old,
+                &old_source,
+                ParseOptions::default(),
+            )?;
+            let new = load_theme(
+                &mut stderr,
+                &new,
+                &new_source,
+                ParseOptions::default(),
+            )?;
+
+            let mut printer =
+                Printer::new(io::stdout(), IndentStyle::default());
+            printer::diff::generate(
+                &mut printer,
+                &old.flat(&mut io::stderr(), DEFAULT_SEPARATOR, false, None)?
+                    .0,
+                &new.flat(&mut io::stderr(), DEFAULT_SEPARATOR, false, None)?
+                    .0,
+            )?;
+            printer.finish()?;
+            Ok(())
+        }
+        Args::Decompile { input, output } => {
+            decompile(&input, output.as_deref())
+        }
+        Args::Init { dir } => Ok(init::init(Path::new(&dir))?),
+        Args::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Args::command(),
+                env!("CARGO_PKG_NAME"),
+                &mut io::stdout(),
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Expands glob patterns in `inputs` (for shells that don't do this).
+fn expand_inputs(inputs: &[OsString]) -> Result<Vec<PathBuf>, CliError> {
+    let mut expanded = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        let pattern = match input.to_str() {
+            Some(s) if !path.exists() && s.contains(['*', '?', '[']) => s,
+            _ => {
+                expanded.push(path.to_owned());
+                continue;
+            }
+        };
+        let len = expanded.len();
+        for entry in glob::glob(pattern).map_err(anyhow::Error::from)? {
+            expanded.push(entry.map_err(|e| CliError::Io(e.into()))?);
+        }
+        if expanded.len() == len {
+            return Err(CliError::Io(anyhow::anyhow!(
+                "No files match '{pattern}'"
+            )));
+        }
+    }
+    Ok(expanded)
+}
+
+/// Returns the path of the theme generated from `input_file` (before the
+/// color scheme is added for variants).
+fn theme_output_path(
+    input_file: &Path,
+    output_dir: &OsStr,
+    out_file: Option<&OsStr>,
+    format: ThemeFormat,
+) -> PathBuf {
+    if let Some(out_file) = out_file {
+        return PathBuf::from(out_file);
+    }
+    let mut output_path = PathBuf::from(output_dir);
+    match input_file.file_stem() {
+        Some(s) => output_path.push(s),
+        None => output_path.push("ChatterinoTheme"),
     }
+    output_path.set_extension(format.extension());
+    output_path
 }
 
 fn generate_theme(
+    diagnostics: &mut impl io::Write,
     input_file: &OsStr,
-    output_dir: &OsStr,
-    timestamp: bool,
-) -> anyhow::Result<()> {
-    let input = fs::read_to_string(input_file)?;
-    let mut parser_input = ParserInput::new(&input);
+    output_path: &Path,
+    options: &ThemeOptions,
+) -> Result<(), CliError> {
+    let input = read_input(input_file)?;
+    let parsed = load_theme(diagnostics, input_file, &input, options.parse)?;
+
+    if parsed.theme.variants.is_empty() {
+        let (flat, replaced) = parsed.flat(
+            diagnostics,
+            options.separator,
+            options.preserve_case,
+            options.fallback,
+        )?;
+        write_theme(&flat, input_file, output_path, options)?;
+        if options.stats {
+            write_theme_stats(
+                diagnostics,
+                output_path,
+                &flat,
+                parsed.theme.count_references(),
+                replaced,
+            )?;
+        }
+        return Ok(());
+    }
+
+    // Name.c2theme -> Name.light.c2theme and Name.dark.c2theme
+    let extension = output_path.extension().unwrap_or_default();
+    for scheme in ColorScheme::ALL {
+        let variant = LoadedTheme {
+            file: parsed.file,
+            theme: parsed.theme.with_variant(scheme),
+        };
+        let mut variant_extension = OsString::from(scheme.name());
+        if !extension.is_empty() {
+            variant_extension.push(".");
+            variant_extension.push(extension);
+        }
+        let (flat, replaced) = variant.flat(
+            diagnostics,
+            options.separator,
+            options.preserve_case,
+            options.fallback,
+        )?;
+        let variant_path = output_path.with_extension(variant_extension);
+        write_theme(&flat, input_file, &variant_path, options)?;
+        if options.stats {
+            write_theme_stats(
+                diagnostics,
+                &variant_path,
+                &flat,
+                variant.theme.count_references(),
+                replaced,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the summary of `--emit-stats` for a generated theme.
+fn write_theme_stats(
+    diagnostics: &mut impl io::Write,
+    output_path: &Path,
+    flat: &FlatTheme,
+    references: usize,
+    replaced: usize,
+) -> io::Result<()> {
+    writeln!(
+        diagnostics,
+        "Stats: {}: {} colors, {references} var() references ({replaced} replaced with --default-color)",
+        output_path.display(),
+        flat.rules.len()
+    )
+}
+
+fn write_theme(
+    flat: &FlatTheme,
+    input_file: &OsStr,
+    output_path: &Path,
+    options: &ThemeOptions,
+) -> Result<(), CliError> {
+    if flat.rules.is_empty() {
+        return Err(CliError::Resolve(anyhow::anyhow!(
+            "{} doesn't define any colors",
+            input_file.to_string_lossy()
+        )));
+    }
+
+    let mut theme = Vec::new();
+    let mut printer = Printer::new(&mut theme, IndentStyle::default());
+    match options.format {
+        ThemeFormat::C2theme => {
+            let comment = options.header_comment.then(|| {
+                format!(
+                    "Generated by {} {} from {}",
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                    Path::new(input_file)
+                        .file_name()
+                        .unwrap_or(input_file)
+                        .to_string_lossy()
+                )
+            });
+            printer::theme::generate(
+                &mut printer,
+                flat,
+                comment.as_deref(),
+                options.order,
+            )?
+        }
+        ThemeFormat::Json => {
+            printer::json::generate(&mut printer, flat, options.order)?
+        }
+    }
+    printer.finish()?;
+    if options.dry_run {
+        return Ok(print_files(&[(output_path, &theme)])?);
+    }
+    create_parent_dir(output_path)?;
+    write_if_changed(output_path, &theme)?;
+
+    if options.timestamp {
+        generate_timestamp(
+            &output_path.with_extension("timestamp"),
+            &[(output_path, &theme)],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn read_input(file: &OsStr) -> Result<String, CliError> {
+    parse::read_source(Path::new(file))
+        .with_context(|| format!("Failed to read {}", file.to_string_lossy()))
+        .map_err(CliError::Io)
+}
+
+/// A parsed style-sheet and the file it was read from.
+struct LoadedTheme<'i> {
+    file: &'i OsStr,
+    theme: Theme<'i>,
+}
+
+impl LoadedTheme<'_> {
+    /// Resolves the theme, printing references that were replaced with
+    /// `fallback` and rules shadowed by `:root` variables to `diagnostics`.
+    ///
+    /// Returns the number of replaced references alongside the theme.
+    fn flat(
+        &self,
+        diagnostics: &mut impl io::Write,
+        separator: &str,
+        preserve_case: bool,
+        fallback: Option<RGBA>,
+    ) -> Result<(FlatTheme<'_>, usize), CliError> {
+        let (flat, replaced) = self
+            .theme
+            .flatten(separator, preserve_case, fallback)
+            .map_err(|e| {
+                CliError::Resolve(anyhow::anyhow!(
+                    "Failed to resolve values in {}: {e}",
+                    self.file.to_string_lossy()
+                ))
+            })?;
+        for e in &replaced {
+            writeln!(
+                diagnostics,
+                "Warning: {}: {e} Using --default-color instead.",
+                self.file.to_string_lossy()
+            )?;
+        }
+        for (name, path) in flat.shadowed_rules(&self.theme, separator) {
+            writeln!(
+                diagnostics,
+                "Warning: {}: 'var({name})' refers to the :root variable, not to the rule {path}.",
+                self.file.to_string_lossy()
+            )?;
+        }
+        Ok((flat, replaced.len()))
+    }
+}
+
+/// Parses the style-sheet `source` (read from `file`), printing parse errors
+/// to `diagnostics`.
+fn load_theme<'i>(
+    diagnostics: &mut impl io::Write,
+    file: &'i OsStr,
+    source: &'i str,
+    options: ParseOptions,
+) -> Result<LoadedTheme<'i>, CliError> {
+    let mut parser_input = ParserInput::new(source);
     let mut parser = cssparser::Parser::new(&mut parser_input);
 
-    let parsed = match parse::parse(&mut parser) {
-        Ok(p) => p,
-        Err(e) => {
-            errors::print_error_with_source(
-                input_file,
-                &input,
-                &errors::format_css_parse_error(&e),
-                &e.location,
-            );
-            std::process::exit(1)
+    // every broken block is reported, not just the first one
+    let (theme, parse_errors) =
+        parse::parse_recovering(&mut parser, Some(Path::new(file)), options);
+    if let (Some(mut theme), true) = (theme, parse_errors.is_empty()) {
+        for name in &theme.meta.ignored {
+            writeln!(
+                diagnostics,
+                "Warning: {}: Ignoring unknown entry '{name}' in @chatterino.",
+                file.to_string_lossy()
+            )?;
         }
-    };
-    let flat = match parsed.flatten() {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("Failed to resolve values: {e}");
-            std::process::exit(1)
+        for duplicate in &theme.duplicates {
+            let duplicate_file = match &duplicate.file {
+                Some(imported) => imported.display().to_string(),
+                None => file.to_string_lossy().into_owned(),
+            };
+            writeln!(
+                diagnostics,
+                "Warning: {duplicate_file} @ line {}, column {}: '{}' is declared multiple times in the block (the last one is used).",
+                duplicate.line,
+                duplicate.column,
+                duplicate.name
+            )?;
         }
+        for name in theme.add_var_prefixes() {
+            writeln!(
+                diagnostics,
+                "Warning: {}: 'var({name})' is missing the '--' prefix (treated as 'var(--{name})').",
+                file.to_string_lossy()
+            )?;
+        }
+        for name in theme.add_root_prefixes() {
+            writeln!(
+                diagnostics,
+                "Warning: {}: The variable '{name}' in :root is missing the '--' prefix (treated as '--{name}').",
+                file.to_string_lossy()
+            )?;
+        }
+        return Ok(LoadedTheme { file, theme });
+    }
+    for e in &parse_errors {
+        errors::print_error_with_source(
+            diagnostics,
+            file,
+            source,
+            &errors::format_css_parse_error(e),
+            &e.location,
+            errors::css_error_end_column(e),
+        );
+    }
+    Err(CliError::Parse(anyhow::anyhow!(
+        "Failed to parse {}",
+        file.to_string_lossy()
+    )))
+}
+
+fn decompile(
+    input_file: &OsStr,
+    output: Option<&OsStr>,
+) -> Result<(), CliError> {
+    let input = read_input(input_file)?;
+    let theme = c2theme::parse(&input)
+        .with_context(|| {
+            format!("Failed to parse {}", input_file.to_string_lossy())
+        })
+        .map_err(CliError::Parse)?;
+
+    let output_path = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(input_file).with_extension("css"),
     };
+    let mut css = Vec::new();
+    let mut printer = Printer::new(&mut css, IndentStyle::Spaces(2));
+    printer::css::generate(&mut printer, &theme)?;
+    printer.finish()?;
+    write_if_changed(&output_path, &css)?;
+    Ok(())
+}
 
-    let mut output_path = PathBuf::from(output_dir);
-    match Path::new(input_file).file_stem() {
-        Some(s) => output_path.push(s),
-        None => output_path.push("ChatterinoTheme"),
+fn parse_color_arg(arg: &str) -> Result<RGBA, String> {
+    let mut input = ParserInput::new(arg);
+    let mut parser = cssparser::Parser::new(&mut input);
+    match parser.parse_entirely(|p| {
+        Color::parse(p).map_err(cssparser::ParseError::<()>::from)
+    }) {
+        Ok(Color::RGBA(color)) => Ok(color),
+        _ => Err(format!("'{arg}' isn't a color")),
     }
-    output_path.set_extension("c2theme");
+}
 
-    let mut imp = std::fs::File::create(&output_path)?;
-    let mut printer = Printer::new(&mut imp);
-    printer::theme::generate(&mut printer, &flat)?;
+/// An empty separator would join `a.bc` and `ab.c` to the same path.
+fn parse_separator(arg: &str) -> Result<String, String> {
+    match arg.is_empty() {
+        true => Err("the separator can't be empty".to_owned()),
+        false => Ok(arg.to_owned()),
+    }
+}
 
-    if timestamp {
-        generate_timestamp(&mut output_path)?;
+/// Checks that the `--name` of the generated files can be used as the name of
+/// the class.
+fn class_name(name: &OsStr) -> Result<String, CliError> {
+    let is_identifier = |s: &str| {
+        s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    match name.to_str() {
+        Some(name) if is_identifier(name) => Ok(name.to_owned()),
+        _ => Err(CliError::Other(anyhow::anyhow!(
+            "--name must be a valid C++ identifier (got '{}')",
+            name.to_string_lossy()
+        ))),
     }
+}
+
+fn read_layouts(layout_files: &[OsString]) -> Result<Vec<String>, CliError> {
+    layout_files.iter().map(|file| read_input(file)).collect()
+}
+
+/// The top level items of the layout to generate (`--only`).
+struct Only<'a> {
+    /// All items are generated if this is empty.
+    items: &'a [String],
+    /// Number the fields of `items` from 0 instead of keeping their ids from
+    /// the full layout.
+    renumber: bool,
+}
 
+/// Parses and flattens the layouts read from `layout_files`.
+///
+/// If `only.items` isn't empty, all other top level items are removed.
+fn load_layout<'a>(
+    layout_files: &[OsString],
+    layouts: &'a [String],
+    only: &Only,
+    preserve_case: bool,
+) -> Result<(Layout<'a>, Vec<FlatLayoutItem<'a>>), CliError> {
+    let layouts = layouts.iter().map(String::as_str).collect::<Vec<_>>();
+    Layout::parse_many(&layouts)
+        .and_then(|mut l| {
+            if only.items.is_empty() {
+                let flat_layout = l.flatten(preserve_case)?;
+                return Ok((l, flat_layout));
+            }
+            if only.renumber {
+                l.retain_items(only.items)?;
+                let flat_layout = l.flatten(preserve_case)?;
+                return Ok((l, flat_layout));
+            }
+            // the ids are assigned while flattening, so the full layout is
+            // flattened to keep them
+            let mut flat_layout = l.flatten(preserve_case)?;
+            l.retain_items(only.items)?;
+            flat_layout.retain(|item| l.items.contains_key(item.name()));
+            Ok((l, flat_layout))
+        })
+        .map_err(|e| {
+            let files = layout_files
+                .iter()
+                .map(|f| f.to_string_lossy())
+                .collect::<Vec<_>>();
+            CliError::Layout(anyhow::anyhow!(
+                "Failed to load {}: {e}",
+                files.join(", ")
+            ))
+        })
+}
+
+/// Prints the key and index of every field in the layout.
+fn list_layout_keys(
+    layout_files: &[OsString],
+    only: &Only,
+    preserve_case: bool,
+) -> Result<(), CliError> {
+    let layouts = read_layouts(layout_files)?;
+    let (_, flat_layout) =
+        load_layout(layout_files, &layouts, only, preserve_case)?;
+    let mut stdout = io::stdout().lock();
+    for (key, id) in
+        layout::keys(&flat_layout, DEFAULT_SEPARATOR, preserve_case)
+    {
+        writeln!(stdout, "{key} = {id}")?;
+    }
     Ok(())
 }
 
 fn generate_code(
-    layout: &OsStr,
-    default_style: &OsStr,
-    output_dir: &OsString,
-    timestamp: bool,
-) -> anyhow::Result<()> {
-    let layout = fs::read_to_string(layout)?;
-    let default_style = fs::read_to_string(default_style)?;
-    let mut parser_input = ParserInput::new(&default_style);
-    let mut parser = cssparser::Parser::new(&mut parser_input);
+    layout_files: &[OsString],
+    only: &Only,
+    default_style: &DefaultStyle,
+    output: &CodeOutput,
+    indent: IndentStyle,
+    options: &CodeOptions,
+) -> Result<(), CliError> {
+    let layouts = read_layouts(layout_files)?;
+    let default_style_file = default_style.file;
+    let source = read_input(default_style_file)?;
+    let parsed = load_theme(
+        &mut io::stderr(),
+        default_style_file,
+        &source,
+        default_style.parse,
+    )?;
+    let (flat, replaced) = parsed.flat(
+        &mut io::stderr(),
+        DEFAULT_SEPARATOR,
+        options.preserve_case,
+        default_style.fallback,
+    )?;
 
-    let parsed = parse::parse(&mut parser).unwrap();
-    let flat = parsed.flatten().unwrap();
+    let (layout, flat_layout) =
+        load_layout(layout_files, &layouts, only, options.preserve_case)?;
 
-    let mut output_path = PathBuf::from(output_dir);
-    output_path.push("GeneratedTheme");
+    let missing =
+        find_missing_rules(&flat_layout, &flat, options.preserve_case);
+    if !missing.is_empty() && flat.rules.is_empty() {
+        // listing every field isn't helpful here
+        return Err(CliError::Resolve(anyhow::anyhow!(
+            "{} doesn't define any colors",
+            default_style_file.to_string_lossy()
+        )));
+    }
+    if !missing.is_empty() {
+        return Err(CliError::Resolve(anyhow::anyhow!(
+            "{} doesn't define: {}",
+            default_style_file.to_string_lossy(),
+            missing.join(", ")
+        )));
+    }
 
-    output_path.set_extension("cpp");
-    let mut imp = std::fs::File::create(&output_path)?;
-    let mut printer = Printer::new(&mut imp);
-    let layout = layout::Layout::parse(&layout).unwrap();
-    generate_impl(&mut printer, &layout, &flat)?;
+    let mut output_path = PathBuf::from(output.dir);
+    output_path.push(output.name);
+    // `with_extension` would replace anything after a dot in the name
+    let with_extension = |ext: &str| {
+        let mut path = output_path.clone().into_os_string();
+        path.push(".");
+        path.push(ext);
+        PathBuf::from(path)
+    };
+    let header_path = with_extension("hpp");
 
-    output_path.set_extension("hpp");
-    let mut header = std::fs::File::create(&output_path)?;
-    let mut printer = Printer::new(&mut header);
-    generate_header(&mut printer, &layout)?;
+    let mut imp = Vec::new();
+    let mut printer = Printer::new(&mut imp, indent);
+    let header_name = header_path.file_name().unwrap_or_default();
+    generate_impl(
+        &mut printer,
+        &header_name.to_string_lossy(),
+        &flat_layout,
+        &flat,
+        options,
+    )?;
+    printer.finish()?;
+    let imp_path = with_extension("cpp");
 
-    if timestamp {
-        generate_timestamp(&mut output_path)?;
+    let mut header = Vec::new();
+    let mut printer = Printer::new(&mut header, indent);
+    generate_header(&mut printer, &layout, &flat_layout, options)?;
+    printer.finish()?;
+
+    let generated: [(&Path, &[u8]); 2] =
+        [(&imp_path, &imp), (&header_path, &header)];
+    if output.stats {
+        eprintln!(
+            "Stats: {} colors in the layout, {} defined by {}, {} var() references ({replaced} replaced with --default-color)",
+            layout.count_items(),
+            flat.rules.len(),
+            default_style_file.to_string_lossy(),
+            parsed.theme.count_references()
+        );
+        for (path, content) in generated {
+            eprintln!(
+                "Stats: {}: {} lines",
+                path.display(),
+                content.iter().filter(|b| **b == b'\n').count()
+            );
+        }
+    }
+    if output.check {
+        let stale = generated
+            .iter()
+            .filter_map(|(path, content)| find_difference(path, content))
+            .collect::<Vec<_>>();
+        if stale.is_empty() {
+            return Ok(());
+        }
+        for message in stale {
+            eprintln!("{message}");
+        }
+        return Err(CliError::Other(anyhow::anyhow!(
+            "The generated files are out of date (run without --check to update them)"
+        )));
+    }
+
+    if output.dry_run {
+        return Ok(print_files(&generated)?);
+    }
+
+    create_parent_dir(&output_path)?;
+    for (path, content) in generated {
+        write_if_changed(path, content)?;
+    }
+    if output.timestamp {
+        generate_timestamp(&with_extension("timestamp"), &generated)?;
     }
 
     Ok(())
 }
 
-fn generate_timestamp(output_file: &mut PathBuf) -> anyhow::Result<()> {
-    output_file.set_extension("timestamp");
-    std::fs::File::create(output_file)?;
+/// Resolves `path` from the directory of `file` (absolute paths are kept).
+fn relative_to(file: &OsStr, path: &OsStr) -> OsString {
+    let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+    dir.join(path).into_os_string()
+}
+
+/// Creates the directory `path` will be written to (if it doesn't exist).
+fn create_parent_dir(path: &Path) -> Result<(), CliError> {
+    let Some(dir) = path.parent() else {
+        return Ok(());
+    };
+    fs::create_dir_all(dir).map_err(|e| {
+        CliError::Io(anyhow::anyhow!(
+            "Failed to create the output directory {}: {e}",
+            dir.display()
+        ))
+    })
+}
+
+/// Writes `content` to `path` unless the file already contains it.
+///
+/// This keeps the modification time of unchanged files, so they don't cause
+/// recompilations.
+fn write_if_changed(path: &Path, content: &[u8]) -> io::Result<()> {
+    match fs::read(path) {
+        Ok(existing) if existing == content => Ok(()),
+        _ => fs::write(path, content),
+    }
+}
+
+/// Writes `files` to stdout, each after a line with its path.
+fn print_files(files: &[(&Path, &[u8])]) -> io::Result<()> {
+    // themes are generated in parallel, so a file is written at once
+    let mut stdout = io::stdout().lock();
+    for (path, content) in files {
+        writeln!(stdout, "=== {} ===", path.display())?;
+        stdout.write_all(content)?;
+    }
     Ok(())
 }
+
+/// Describes how the file at `path` differs from `content` (if it does).
+fn find_difference(path: &Path, content: &[u8]) -> Option<String> {
+    let Ok(existing) = fs::read(path) else {
+        return Some(format!("{} doesn't exist", path.display()));
+    };
+    if existing == content {
+        return None;
+    }
+    let mut existing_lines = existing.split(|&c| c == b'\n');
+    let mut lines = content.split(|&c| c == b'\n');
+    let mut line = 1;
+    while existing_lines.next() == lines.next() {
+        line += 1;
+    }
+    Some(format!(
+        "{} is out of date (first difference in line {line})",
+        path.display()
+    ))
+}
+
+/// Writes the current time and the SHA-256 of each `generated` file
+/// (in the format of `sha256sum`) to `timestamp_file`.
+fn generate_timestamp(
+    timestamp_file: &Path,
+    generated: &[(&Path, &[u8])],
+) -> io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+        .as_secs();
+    let mut content = format!("{now}\n");
+    for (file, generated) in generated {
+        let hash = Sha256::digest(generated);
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        content.push_str(&format!("{hash:x}  {name}\n"));
+    }
+    fs::write(timestamp_file, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads and flattens `source`, returning the flattened rules and
+    /// everything written to the diagnostics.
+    fn load(source: &str) -> (Vec<String>, String) {
+        let mut diagnostics = Vec::new();
+        let file = OsStr::new("Theme.css");
+        let loaded =
+            load_theme(&mut diagnostics, file, source, ParseOptions::default())
+                .unwrap();
+        let (flat, replaced) = loaded
+            .flat(&mut diagnostics, DEFAULT_SEPARATOR, false, None)
+            .unwrap();
+        assert_eq!(replaced, 0);
+        let mut rules = flat.rules.into_keys().collect::<Vec<_>>();
+        rules.sort_unstable();
+        (rules, String::from_utf8(diagnostics).unwrap())
+    }
+
+    #[test]
+    fn unknown_meta_is_a_warning() {
+        let (rules, diagnostics) = load(
+            r#"@chatterino { author: "a"; icon-set: "dark"; tags: "x"; }
+            a { b: #fff; }"#,
+        );
+        assert_eq!(rules, ["a.b"]);
+        assert_eq!(
+            diagnostics,
+            "Warning: Theme.css: Ignoring unknown entry 'tags' in @chatterino.\n"
+        );
+    }
+
+    #[test]
+    fn duplicate_declaration_is_a_warning() {
+        let (rules, diagnostics) = load(
+            r#"@chatterino { author: "a"; icon-set: "dark"; }
+a { b: #fff; b: #000; }"#,
+        );
+        assert_eq!(rules, ["a.b"]);
+        assert_eq!(
+            diagnostics,
+            "Warning: Theme.css @ line 2, column 17: 'b' is declared multiple times in the block (the last one is used).\n"
+        );
+    }
+
+    #[test]
+    fn strict_keeps_missing_colors_an_error() {
+        for subcommand in ["code", "theme"] {
+            let args = Args::try_parse_from([
+                "cstylegen",
+                subcommand,
+                "Theme.css",
+                "--strict",
+                "--default-color",
+                "magenta",
+            ]);
+            let Err(e) = args else {
+                panic!(
+                    "{subcommand}: --strict and --default-color were accepted"
+                );
+            };
+            assert_eq!(e.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+
+        let source = r#"@chatterino { author: "a"; icon-set: "dark"; }
+a { b: var(--missing); }"#;
+        let file = OsStr::new("Theme.css");
+        let loaded =
+            load_theme(&mut io::sink(), file, source, ParseOptions::STRICT)
+                .unwrap();
+        let Err(e) = loaded.theme.flatten(DEFAULT_SEPARATOR, false, None)
+        else {
+            panic!("the missing color was resolved");
+        };
+        assert!(matches!(e, model::FlattenError::MissingColor(..)), "{e}");
+    }
+
+    #[test]
+    fn missing_or_empty_root() {
+        let meta = r#"@chatterino { author: "a"; icon-set: "dark"; }"#;
+        for root in [
+            "",
+            ":root {}",
+            "@media (prefers-color-scheme: dark) { :root {} }",
+        ] {
+            let (rules, diagnostics) =
+                load(&format!("{meta}\n{root}\na {{ b: #fff; c: red; }}"));
+            assert_eq!(rules, ["a.b", "a.c"], "{root}");
+            assert_eq!(diagnostics, "", "{root}");
+        }
+    }
+
+    #[test]
+    fn root_without_prefix() {
+        let (rules, diagnostics) = load(
+            r#"@chatterino { author: "a"; icon-set: "dark"; }
+            :root { accent: #fff; --text: var(accent); }
+            a { b: var(--accent); c: var(accent); d: var(--text); }"#,
+        );
+        assert_eq!(rules, ["a.b", "a.c", "a.d"]);
+        assert_eq!(
+            diagnostics,
+            "Warning: Theme.css: 'var(accent)' is missing the '--' prefix (treated as 'var(--accent)').\n\
+             Warning: Theme.css: The variable 'accent' in :root is missing the '--' prefix (treated as '--accent').\n"
+        );
+    }
+
+    #[test]
+    fn only_keeps_the_ids() {
+        let layouts = [include_str!("../tests/golden/layout.yml").to_owned()];
+        let items = ["tabs".to_owned()];
+        let ids = |renumber| {
+            let only = Only {
+                items: &items,
+                renumber,
+            };
+            let (layout, flat_layout) =
+                load_layout(&["layout.yml".into()], &layouts, &only, false)
+                    .unwrap();
+            assert_eq!(
+                layout.items.keys().copied().collect::<Vec<_>>(),
+                ["tabs"]
+            );
+            layout::keys(&flat_layout, DEFAULT_SEPARATOR, false)
+                .into_iter()
+                .map(|(_, id)| id)
+                .collect::<Vec<_>>()
+        };
+
+        // `colors` has three fields
+        assert_eq!(ids(false), (3..10).collect::<Vec<_>>());
+        assert_eq!(ids(true), (0..7).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_separator_is_rejected() {
+        let parse = |separator| {
+            Args::try_parse_from(["cstylegen", "theme", separator, "a.css"])
+        };
+        let e = parse("--separator=").unwrap_err();
+        assert_eq!(e.kind(), clap::error::ErrorKind::ValueValidation);
+        assert!(parse("--separator=/").is_ok());
+    }
+
+    #[test]
+    fn failed_themes_keep_their_exit_code() {
+        let args = Args::try_parse_from([
+            "cstylegen",
+            "theme",
+            "--dry-run",
+            "missing/A.css",
+            "missing/B.css",
+        ])
+        .unwrap();
+        let e = run(args).unwrap_err();
+        assert!(matches!(e, CliError::Io(_)));
+        assert_eq!(e.to_string(), "2 of 2 themes couldn't be generated");
+    }
+}
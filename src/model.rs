@@ -1,5 +1,11 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+
 use ahash::AHashMap;
 use cssparser::{CowRcStr, RGBA};
+use indexmap::IndexMap;
 
 use crate::combinator::combine_path;
 
@@ -7,20 +13,91 @@ use crate::combinator::combine_path;
 pub struct ChatterinoMeta<'i> {
     pub author: CowRcStr<'i>,
     pub icon_set: CowRcStr<'i>,
+    pub name: Option<CowRcStr<'i>>,
+    pub version: Option<CowRcStr<'i>>,
+    pub description: Option<CowRcStr<'i>>,
+    /// Other entries of the metadata, written as-is.
+    pub extras: BTreeMap<String, String>,
+    /// Names of unknown entries that were skipped (see
+    /// [`crate::parse::UnknownMeta::Ignore`]).
+    pub ignored: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RuleValue<'i> {
-    ColorRef(CowRcStr<'i>),
+    /// `var(--name)`, optionally with a fallback (`var(--name, #fff)`).
+    ColorRef(CowRcStr<'i>, Option<Box<RuleValue<'i>>>),
     Color(cssparser::RGBA),
 }
 
-pub type RuleMap<'i> = AHashMap<CowRcStr<'i>, Rule<'i>>;
+/// Rules in the order they appear in the style-sheet.
+pub type RuleMap<'i> = IndexMap<CowRcStr<'i>, Rule<'i>, ahash::RandomState>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Rule<'i> {
-    Value(RuleValue<'i>),
+    /// A value and how it was written (e.g. `rebeccapurple` or
+    /// `var(--accent)`).
+    Value(RuleValue<'i>, CowRcStr<'i>),
     Nested(RuleMap<'i>),
+    /// `alpha` (or `opacity`): multiplied into the alpha of the values in
+    /// the same block (`0.0..=1.0`).
+    Alpha(f32),
+}
+
+/// Copies `s` so it doesn't borrow from the source anymore.
+pub fn owned_str<'o>(s: &str) -> CowRcStr<'o> {
+    CowRcStr::from(s.to_owned())
+}
+
+/// Adds the `--` prefix to the name of a variable if it's missing.
+pub fn with_var_prefix<'i>(name: &CowRcStr<'i>) -> CowRcStr<'i> {
+    match name.starts_with("--") {
+        true => name.clone(),
+        false => CowRcStr::from(format!("--{name}")),
+    }
+}
+
+impl<'i> ChatterinoMeta<'i> {
+    pub fn into_owned<'o>(self) -> ChatterinoMeta<'o> {
+        ChatterinoMeta {
+            author: owned_str(&self.author),
+            icon_set: owned_str(&self.icon_set),
+            name: self.name.as_deref().map(owned_str),
+            version: self.version.as_deref().map(owned_str),
+            description: self.description.as_deref().map(owned_str),
+            extras: self.extras,
+            ignored: self.ignored,
+        }
+    }
+}
+
+impl<'i> RuleValue<'i> {
+    pub fn into_owned<'o>(self) -> RuleValue<'o> {
+        match self {
+            RuleValue::ColorRef(name, fallback) => RuleValue::ColorRef(
+                owned_str(&name),
+                fallback.map(|f| Box::new(f.into_owned())),
+            ),
+            RuleValue::Color(c) => RuleValue::Color(c),
+        }
+    }
+}
+
+impl<'i> Rule<'i> {
+    pub fn into_owned<'o>(self) -> Rule<'o> {
+        match self {
+            Rule::Value(value, source) => {
+                Rule::Value(value.into_owned(), owned_str(&source))
+            }
+            Rule::Nested(rules) => Rule::Nested(
+                rules
+                    .into_iter()
+                    .map(|(k, v)| (owned_str(&k), v.into_owned()))
+                    .collect(),
+            ),
+            Rule::Alpha(alpha) => Rule::Alpha(alpha),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -28,63 +105,518 @@ pub struct Theme<'i> {
     pub meta: ChatterinoMeta<'i>,
     pub colors: CustomColors<'i>,
     pub rules: RuleMap<'i>,
+    /// Overrides from `@media (prefers-color-scheme: ..)` blocks.
+    pub variants: IndexMap<ColorScheme, Variant<'i>, ahash::RandomState>,
+    /// Names declared more than once in a block, so they can be reported.
+    pub duplicates: Vec<DuplicateDeclaration>,
+}
+
+/// A name that was declared more than once in a block (the later declaration
+/// is used).
+#[derive(Debug, Clone)]
+pub struct DuplicateDeclaration {
+    pub name: String,
+    /// The imported file it was declared in (`None` for the file itself).
+    pub file: Option<PathBuf>,
+    /// The (1-based) line and column of the later declaration's value.
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    pub const ALL: [ColorScheme; 2] = [ColorScheme::Light, ColorScheme::Dark];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorScheme::Light => "light",
+            ColorScheme::Dark => "dark",
+        }
+    }
+}
+
+/// Custom colors and rules that override the ones of a theme.
+#[derive(Debug, Default)]
+pub struct Variant<'i> {
+    pub colors: CustomColors<'i>,
+    pub rules: RuleMap<'i>,
+}
+
+impl<'i> Variant<'i> {
+    pub fn into_owned<'o>(self) -> Variant<'o> {
+        Variant {
+            colors: self
+                .colors
+                .into_iter()
+                .map(|(k, v)| (owned_str(&k), v.into_owned()))
+                .collect(),
+            rules: self
+                .rules
+                .into_iter()
+                .map(|(k, v)| (owned_str(&k), v.into_owned()))
+                .collect(),
+        }
+    }
 }
 
-pub type CustomColors<'i> = AHashMap<CowRcStr<'i>, cssparser::RGBA>;
+/// Custom colors (from `:root`) in the order they appear in the style-sheet.
+///
+/// These can reference other custom colors.
+pub type CustomColors<'i> =
+    IndexMap<CowRcStr<'i>, RuleValue<'i>, ahash::RandomState>;
 
 #[derive(Debug)]
 pub struct FlatTheme<'i> {
     pub meta: ChatterinoMeta<'i>,
     pub rules: AHashMap<String, RGBA>,
+    /// How the rules were written in the style-sheet (if they came from
+    /// one).
+    pub sources: AHashMap<String, String>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum FlattenError<'i> {
     #[error("'{0}' was used in {1} but never defined anywhere.")]
     MissingColor(CowRcStr<'i>, String),
+    #[error("'{0}' (used in {1}) matches multiple rules.")]
+    AmbiguousReference(CowRcStr<'i>, String),
+    #[error("Circular reference: {0}")]
+    CircularReference(String),
 }
 
 impl<'i> Theme<'i> {
-    pub fn flatten(&self) -> Result<FlatTheme, FlattenError<'i>> {
+    /// Returns this theme with the overrides for `scheme` applied.
+    ///
+    /// Nested rules are merged, everything else is replaced.
+    pub fn with_variant(&self, scheme: ColorScheme) -> Theme<'i> {
+        fn merge_rules<'i>(base: &mut RuleMap<'i>, overrides: &RuleMap<'i>) {
+            for (name, rule) in overrides {
+                match (base.get_mut(name), rule) {
+                    (Some(Rule::Nested(base)), Rule::Nested(overrides)) => {
+                        merge_rules(base, overrides)
+                    }
+                    _ => {
+                        base.insert(name.clone(), rule.clone());
+                    }
+                }
+            }
+        }
+
+        let mut theme = Theme {
+            meta: self.meta.clone(),
+            colors: self.colors.clone(),
+            rules: self.rules.clone(),
+            variants: Default::default(),
+            duplicates: self.duplicates.clone(),
+        };
+        if let Some(variant) = self.variants.get(&scheme) {
+            theme.colors.extend(
+                variant.colors.iter().map(|(k, v)| (k.clone(), v.clone())),
+            );
+            merge_rules(&mut theme.rules, &variant.rules);
+        }
+        theme
+    }
+
+    /// Adds the `--` prefix to `var()` names that don't have it (e.g.
+    /// `var(accent)` becomes `var(--accent)`), so they match the names of
+    /// the custom colors.
+    ///
+    /// Returns the names that were changed.
+    pub fn add_var_prefixes(&mut self) -> BTreeSet<String> {
+        fn fix_value(value: &mut RuleValue, fixed: &mut BTreeSet<String>) {
+            if let RuleValue::ColorRef(name, fallback) = value {
+                if !name.starts_with("--") {
+                    fixed.insert(name.to_string());
+                    *name = with_var_prefix(name);
+                }
+                if let Some(fallback) = fallback {
+                    fix_value(fallback, fixed);
+                }
+            }
+        }
+        fn fix_rules(rules: &mut RuleMap, fixed: &mut BTreeSet<String>) {
+            for rule in rules.values_mut() {
+                match rule {
+                    Rule::Value(value, _) => fix_value(value, fixed),
+                    Rule::Nested(rules) => fix_rules(rules, fixed),
+                    Rule::Alpha(_) => (),
+                }
+            }
+        }
+
+        let mut fixed = BTreeSet::new();
+        let variants = self.variants.values_mut();
+        for (colors, rules) in
+            std::iter::once((&mut self.colors, &mut self.rules))
+                .chain(variants.map(|v| (&mut v.colors, &mut v.rules)))
+        {
+            for value in colors.values_mut() {
+                fix_value(value, &mut fixed);
+            }
+            fix_rules(rules, &mut fixed);
+        }
+        fixed
+    }
+
+    /// Adds the `--` prefix to the names of custom colors that don't have it
+    /// (e.g. `accent` in `:root` becomes `--accent`), so `var()`s (see
+    /// [`Theme::add_var_prefixes`]) can reference them.
+    ///
+    /// Returns the names that were changed.
+    pub fn add_root_prefixes(&mut self) -> BTreeSet<String> {
+        let mut fixed = BTreeSet::new();
+        let variants = self.variants.values_mut().map(|v| &mut v.colors);
+        for colors in std::iter::once(&mut self.colors).chain(variants) {
+            if colors.keys().all(|name| name.starts_with("--")) {
+                continue;
+            }
+            // the parser treats `name` and `--name` in one file as the same
+            // variable; across imports, the later one overrides the earlier
+            // one like any other variable
+            *colors = std::mem::take(colors)
+                .into_iter()
+                .map(|(name, value)| {
+                    if !name.starts_with("--") {
+                        fixed.insert(name.to_string());
+                    }
+                    (with_var_prefix(&name), value)
+                })
+                .collect();
+        }
+        fixed
+    }
+
+    /// Counts the `var()`s in the rules and custom colors (not including
+    /// fallbacks and variants).
+    pub fn count_references(&self) -> usize {
+        fn count_rules(rules: &RuleMap) -> usize {
+            rules
+                .values()
+                .map(|rule| match rule {
+                    Rule::Value(RuleValue::ColorRef(..), _) => 1,
+                    Rule::Nested(rules) => count_rules(rules),
+                    _ => 0,
+                })
+                .sum()
+        }
+
+        let colors = self
+            .colors
+            .values()
+            .filter(|value| matches!(value, RuleValue::ColorRef(..)))
+            .count();
+        colors + count_rules(&self.rules)
+    }
+
+    /// Resolves all rules to their colors.
+    ///
+    /// A `var(--name)` is looked up in the custom colors (`:root`) first.
+    /// If there's no such color, it refers to the rule whose path matches
+    /// `name` (e.g. `var(--tabs-regular-text)` refers to `tabs.regular.text`).
+    /// Custom colors can only reference other custom colors.
+    ///
+    /// The parts of a path are joined with `separator`. They're lowercased
+    /// unless `preserve_case` is set.
+    ///
+    /// If a `fallback` is given, references to colors that don't exist
+    /// resolve to it instead of failing. These references are returned
+    /// alongside the theme.
+    pub fn flatten(
+        &self,
+        separator: &str,
+        preserve_case: bool,
+        fallback: Option<RGBA>,
+    ) -> Result<(FlatTheme<'_>, Vec<FlattenError<'i>>), FlattenError<'i>> {
         let mut flat = FlatTheme {
             meta: self.meta.clone(),
             rules: Default::default(),
+            sources: Default::default(),
+        };
+        let mut replaced = vec![];
+        let mut on_missing = |e| match fallback {
+            Some(color) => {
+                replaced.push(e);
+                Ok(color)
+            }
+            None => Err(e),
         };
-        inner_flatten(&mut flat.rules, "", &self.rules, &self.colors)?;
-        Ok(flat)
+        let colors = resolve_custom_colors(&self.colors, &mut on_missing)?;
+        let mut rule_refs = AHashMap::new();
+        inner_flatten(
+            &mut flat,
+            &mut rule_refs,
+            "",
+            &self.rules,
+            &colors,
+            separator,
+            preserve_case,
+        )?;
+        resolve_rule_refs(
+            &mut flat.rules,
+            rule_refs,
+            &colors,
+            separator,
+            &mut on_missing,
+        )?;
+        Ok((flat, replaced))
     }
 }
 
-fn inner_flatten<'i>(
+impl<'i> FlatTheme<'i> {
+    /// Returns the custom colors of `theme` (the theme this was flattened
+    /// from) whose `var()` matches a rule, paired with that rule.
+    ///
+    /// These references always resolve to the custom color, so the rule
+    /// can't be referenced.
+    pub fn shadowed_rules<'t>(
+        &'t self,
+        theme: &'t Theme,
+        separator: &str,
+    ) -> Vec<(&'t str, &'t str)> {
+        let colors = theme
+            .colors
+            .keys()
+            .map(|name| (reference_key(name, separator), name.as_ref()))
+            .collect::<AHashMap<_, _>>();
+        let mut shadowed = self
+            .rules
+            .keys()
+            .filter_map(|path| {
+                let name = colors.get(&reference_key(path, separator))?;
+                Some((*name, path.as_str()))
+            })
+            .collect::<Vec<_>>();
+        shadowed.sort_unstable();
+        shadowed
+    }
+}
+
+/// Called with a [`FlattenError::MissingColor`], returns the color to use
+/// instead (or the error).
+type OnMissing<'f, 'i> =
+    dyn FnMut(FlattenError<'i>) -> Result<RGBA, FlattenError<'i>> + 'f;
+
+/// Maps a path (`tabs.regular.text`) or a variable (`--tabs-regular-text`)
+/// to a key which is the same for both (`tabsregulartext`).
+///
+/// The key is always lowercase, so references match regardless of the case.
+fn reference_key(name: &str, separator: &str) -> String {
+    combine_path("", &name.replace(separator, ""), separator, false)
+}
+
+/// What references of rules are looked up in.
+struct RefContext<'a, 'i> {
+    rule_refs: &'a AHashMap<String, (RuleValue<'i>, f32)>,
+    /// Maps reference keys to paths (`None` marks keys that match multiple
+    /// paths).
+    paths: &'a AHashMap<String, Option<&'a str>>,
+    colors: &'a AHashMap<&'a str, RGBA>,
+    separator: &'a str,
+}
+
+fn resolve_rule_refs<'i>(
     map: &mut AHashMap<String, RGBA>,
+    rule_refs: AHashMap<String, (RuleValue<'i>, f32)>,
+    colors: &AHashMap<&str, RGBA>,
+    separator: &str,
+    on_missing: &mut OnMissing<'_, 'i>,
+) -> Result<(), FlattenError<'i>> {
+    let mut paths = AHashMap::<String, Option<&str>>::new();
+    for path in map.keys().chain(rule_refs.keys()) {
+        paths
+            .entry(reference_key(path, separator))
+            .and_modify(|e| *e = None)
+            .or_insert(Some(path));
+    }
+
+    fn resolve<'i>(
+        path: &str,
+        ctx: &RefContext<'_, 'i>,
+        resolved: &mut AHashMap<String, RGBA>,
+        stack: &mut Vec<String>,
+        on_missing: &mut OnMissing<'_, 'i>,
+    ) -> Result<RGBA, FlattenError<'i>> {
+        if let Some(color) = resolved.get(path) {
+            return Ok(*color);
+        }
+        let (value, alpha) = &ctx.rule_refs[path];
+        // the reference and its fallbacks are tried in order
+        let mut current = value;
+        let color = loop {
+            let (name, fallback) = match current {
+                RuleValue::Color(c) => break *c,
+                RuleValue::ColorRef(name, fallback) => (name, fallback),
+            };
+            if let Some(color) = ctx.colors.get(name.as_ref()) {
+                break *color;
+            }
+            match ctx.paths.get(&reference_key(name, ctx.separator)) {
+                Some(Some(target)) => {
+                    stack.push(path.to_owned());
+                    if stack.iter().any(|p| p == target) {
+                        stack.push(target.to_string());
+                        return Err(FlattenError::CircularReference(
+                            stack.join(" -> "),
+                        ));
+                    }
+                    let color =
+                        resolve(target, ctx, resolved, stack, on_missing)?;
+                    stack.pop();
+                    break color;
+                }
+                Some(None) => {
+                    return Err(FlattenError::AmbiguousReference(
+                        name.clone(),
+                        path.to_owned(),
+                    ))
+                }
+                None => match fallback {
+                    Some(fallback) => current = fallback.as_ref(),
+                    None => {
+                        break on_missing(FlattenError::MissingColor(
+                            name.clone(),
+                            path.to_owned(),
+                        ))?
+                    }
+                },
+            }
+        };
+        let color = multiply_alpha(color, *alpha);
+        resolved.insert(path.to_owned(), color);
+        Ok(color)
+    }
+
+    let ctx = RefContext {
+        rule_refs: &rule_refs,
+        paths: &paths,
+        colors,
+        separator,
+    };
+    let mut resolved = map.clone();
+    for path in rule_refs.keys() {
+        resolve(path, &ctx, &mut resolved, &mut vec![], on_missing)?;
+    }
+    *map = resolved;
+    Ok(())
+}
+
+/// Resolves custom colors that reference other custom colors.
+fn resolve_custom_colors<'c, 'i>(
+    colors: &'c CustomColors<'i>,
+    on_missing: &mut OnMissing<'_, 'i>,
+) -> Result<AHashMap<&'c str, RGBA>, FlattenError<'i>> {
+    fn resolve<'c, 'i>(
+        name: &'c str,
+        colors: &'c CustomColors<'i>,
+        resolved: &mut AHashMap<&'c str, RGBA>,
+        stack: &mut Vec<&'c str>,
+        on_missing: &mut OnMissing<'_, 'i>,
+    ) -> Result<RGBA, FlattenError<'i>> {
+        if let Some(color) = resolved.get(name) {
+            return Ok(*color);
+        }
+        // the reference and its fallbacks are tried in order
+        let mut current = &colors[name];
+        let color = loop {
+            let (target, fallback) = match current {
+                RuleValue::Color(c) => break *c,
+                RuleValue::ColorRef(target, fallback) => (target, fallback),
+            };
+            if let Some((target, _)) = colors.get_key_value(target.as_ref()) {
+                stack.push(name);
+                if stack.contains(&target.as_ref()) {
+                    stack.push(target);
+                    return Err(FlattenError::CircularReference(
+                        stack.join(" -> "),
+                    ));
+                }
+                let color =
+                    resolve(target, colors, resolved, stack, on_missing)?;
+                stack.pop();
+                break color;
+            }
+            match fallback {
+                Some(fallback) => current = fallback.as_ref(),
+                None => {
+                    break on_missing(FlattenError::MissingColor(
+                        target.clone(),
+                        format!(":root ({name})"),
+                    ))?
+                }
+            }
+        };
+        resolved.insert(name, color);
+        Ok(color)
+    }
+
+    let mut resolved = AHashMap::new();
+    for name in colors.keys() {
+        resolve(name, colors, &mut resolved, &mut vec![], on_missing)?;
+    }
+    Ok(resolved)
+}
+
+fn inner_flatten<'i>(
+    flat: &mut FlatTheme,
+    rule_refs: &mut AHashMap<String, (RuleValue<'i>, f32)>,
     prefix: &str,
     rules: &RuleMap<'i>,
-    colors: &CustomColors,
+    colors: &AHashMap<&str, RGBA>,
+    separator: &str,
+    preserve_case: bool,
 ) -> Result<(), FlattenError<'i>> {
+    let alpha = rules
+        .values()
+        .filter_map(|rule| match rule {
+            Rule::Alpha(alpha) => Some(*alpha),
+            _ => None,
+        })
+        .product();
     for (name, rule) in rules {
         match rule {
-            Rule::Value(value) => {
-                let path = combine_path(prefix, name);
+            Rule::Value(value, source) => {
+                let path = combine_path(prefix, name, separator, preserve_case);
+                flat.sources.insert(path.clone(), source.to_string());
                 let value = match value {
-                    RuleValue::ColorRef(name) => {
-                        let Some(color) = colors.get(name) else {
-                            return Err(FlattenError::MissingColor(name.clone(), path));
+                    RuleValue::ColorRef(name, _) => {
+                        let Some(color) = colors.get(name.as_ref()) else {
+                            // references another rule (or uses a fallback),
+                            // resolved later
+                            rule_refs.insert(path, (value.clone(), alpha));
+                            continue;
                         };
                         *color
                     }
                     RuleValue::Color(c) => *c,
                 };
-                map.insert(path, value);
+                flat.rules.insert(path, multiply_alpha(value, alpha));
             }
             Rule::Nested(nested) => {
                 inner_flatten(
-                    map,
-                    &combine_path(prefix, name),
+                    flat,
+                    rule_refs,
+                    &combine_path(prefix, name, separator, preserve_case),
                     nested,
                     colors,
+                    separator,
+                    preserve_case,
                 )?;
             }
+            Rule::Alpha(_) => (),
         }
     }
     Ok(())
 }
+
+fn multiply_alpha(color: RGBA, factor: f32) -> RGBA {
+    RGBA {
+        alpha: (color.alpha as f32 * factor).round() as u8,
+        ..color
+    }
+}
@@ -1,3 +1,30 @@
 mod parsers;
 
+use std::{fs, io, path::Path};
+
 pub use parsers::*;
+
+/// Reads the text file at `path`.
+///
+/// A leading UTF-8 byte order mark is removed, as editors on Windows like to
+/// add one. Other encodings are rejected with an error saying so.
+pub fn read_source(path: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(b"\xFF\xFE") || bytes.starts_with(b"\xFE\xFF") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the file is encoded as UTF-16 (it has to be UTF-8)",
+        ));
+    }
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(source) => Ok(source.to_owned()),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the file isn't valid UTF-8 (invalid byte at offset {})",
+                e.valid_up_to()
+            ),
+        )),
+    }
+}
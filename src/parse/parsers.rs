@@ -1,13 +1,23 @@
-use std::collections::hash_map;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
+use ahash::{AHashMap, AHashSet};
 use cssparser::{
-    AtRuleParser, BasicParseError, Color, CowRcStr, DeclarationListParser,
-    DeclarationParser, QualifiedRuleParser, RuleListParser,
-    _cssparser_internal_to_lowercase, RGBA,
+    _cssparser_internal_to_lowercase, AtRuleParser, BasicParseError, Color,
+    CowRcStr, DeclarationListParser, DeclarationParser, ParserInput,
+    QualifiedRuleParser, RuleListParser, SourceLocation, Token,
 };
+use indexmap::IndexMap;
 
-use crate::model::{
-    ChatterinoMeta, CustomColors, Rule, RuleMap, RuleValue, Theme,
+use crate::{
+    errors,
+    model::{
+        owned_str, with_var_prefix, ChatterinoMeta, ColorScheme, CustomColors,
+        DuplicateDeclaration, Rule, RuleMap, RuleValue, Theme, Variant,
+    },
 };
 
 macro_rules! bail_rule {
@@ -25,16 +35,71 @@ pub enum ParseError<'a> {
     UnexpectedMeta(CowRcStr<'a>),
     #[error("Missing '{0}' in meta")]
     MissingMetaItem(&'static str),
+    #[error("'{0}' in meta must not be empty")]
+    EmptyMetaItem(&'static str),
+    #[error("Expected a number or percentage between 0 and 1")]
+    InvalidAlpha,
+    #[error(
+        "{0}-digit hex colors aren't valid (expected 3, 4, 6, or 8 digits)"
+    )]
+    InvalidHexColor(usize),
+    #[error("var() fallbacks can't be nested more than 8 levels deep")]
+    VarTooDeep,
     #[error("'currentColor' isn't supported")]
     CurrentColorFound,
     #[error("Expected a @chatterino metadata block")]
     MissingMetaBlock,
-    #[error("Found duplicate @chatterino metadata block")]
-    DuplicateMetaBlock,
-    #[error("Found duplicate :root block")]
-    DuplicateRootBlock,
-    #[error("Found duplicate block ('{0}')")]
-    DuplicateBlock(CowRcStr<'a>),
+    #[error(
+        "Found duplicate @chatterino metadata block (first defined at line {0}, redefined at line {1})"
+    )]
+    DuplicateMetaBlock(u32, u32),
+    #[error(
+        "'{0}' is already defined in the :root block at line {1} (redefined at line {2})"
+    )]
+    DuplicateVariable(CowRcStr<'a>, u32, u32),
+    #[error(
+        "Found duplicate block ('{0}', first defined at line {1}, redefined at line {2})"
+    )]
+    DuplicateBlock(CowRcStr<'a>, u32, u32),
+    #[error("Found duplicate declaration ('{0}')")]
+    DuplicateDeclaration(CowRcStr<'a>),
+    #[error("Only :root and regular blocks are allowed in @media")]
+    UnexpectedInMedia,
+    #[error("Failed to import '{0}': {1}")]
+    ImportFailed(String, String),
+    #[error("Circular import: {0}")]
+    ImportCycle(String),
+}
+
+/// What to do with unknown entries of `@chatterino`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownMeta {
+    /// Skip them. Their names are kept in [`ChatterinoMeta::ignored`], so
+    /// they can be reported.
+    #[default]
+    Ignore,
+    /// Keep them in [`ChatterinoMeta::extras`] (only strings are allowed).
+    Keep,
+    /// Fail with [`ParseError::UnexpectedMeta`].
+    Error,
+}
+
+/// How to handle input that's accepted with a warning by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// What to do with unknown entries of `@chatterino`.
+    pub unknown_meta: UnknownMeta,
+    /// Fail with [`ParseError::DuplicateDeclaration`] instead of adding
+    /// duplicates to [`Theme::duplicates`].
+    pub strict: bool,
+}
+
+impl ParseOptions {
+    /// Fails on everything that's only a warning by default.
+    pub const STRICT: Self = Self {
+        unknown_meta: UnknownMeta::Error,
+        strict: true,
+    };
 }
 
 type SingleRule<'i> = (CowRcStr<'i>, Rule<'i>);
@@ -43,11 +108,68 @@ enum TopLevelItem<'i> {
     Meta(ChatterinoMeta<'i>),
     Root(CustomColors<'i>),
     Regular(SingleRule<'i>),
+    Import(Box<ThemeParserState<'i>>),
+    Media(ColorScheme, Variant<'i>, RuleLines<'i>),
 }
 
-struct RegularRuleParser;
+/// A top level item and where it starts.
+type Located<'i> = (SourceLocation, TopLevelItem<'i>);
+
+/// The (1-based) lines blocks were defined on, used to report duplicates.
+type RuleLines<'i> = AHashMap<CowRcStr<'i>, u32>;
+
+/// Checks that each name is only declared once in a block.
+struct DeclaredNames<'i, 'a> {
+    names: AHashSet<CowRcStr<'i>>,
+    /// Names that are declared again are added here (unless `strict` is
+    /// set).
+    duplicates: &'a mut Vec<DuplicateDeclaration>,
+    strict: bool,
+}
+
+impl<'i, 'a> DeclaredNames<'i, 'a> {
+    fn new(
+        duplicates: &'a mut Vec<DuplicateDeclaration>,
+        strict: bool,
+    ) -> Self {
+        Self {
+            names: Default::default(),
+            duplicates,
+            strict,
+        }
+    }
 
-impl<'i> DeclarationParser<'i> for RegularRuleParser {
+    fn add<'t>(
+        &mut self,
+        name: &CowRcStr<'i>,
+        input: &mut cssparser::Parser<'i, 't>,
+    ) -> Result<(), cssparser::ParseError<'i, ParseError<'i>>> {
+        if self.names.insert(name.clone()) {
+            return Ok(());
+        }
+        if self.strict {
+            return Err(input.new_custom_error(
+                ParseError::DuplicateDeclaration(name.clone()),
+            ));
+        }
+        // reported where the value starts
+        input.skip_whitespace();
+        let location = input.current_source_location();
+        self.duplicates.push(DuplicateDeclaration {
+            name: name.to_string(),
+            file: None,
+            line: location.line + 1,
+            column: location.column,
+        });
+        Ok(())
+    }
+}
+
+struct RegularRuleParser<'i, 'a> {
+    names: DeclaredNames<'i, 'a>,
+}
+
+impl<'i> DeclarationParser<'i> for RegularRuleParser<'i, '_> {
     type Declaration = (CowRcStr<'i>, Rule<'i>);
 
     type Error = ParseError<'i>;
@@ -57,25 +179,18 @@ impl<'i> DeclarationParser<'i> for RegularRuleParser {
         name: cssparser::CowRcStr<'i>,
         p: &mut cssparser::Parser<'i, 't>,
     ) -> Result<Self::Declaration, cssparser::ParseError<'i, Self::Error>> {
-        let var: Result<CowRcStr, cssparser::ParseError<ParseError<'i>>> = p
-            .try_parse(|p| {
-                p.expect_function_matching("var")?;
-                p.parse_nested_block(|p| {
-                    let name = p.expect_ident_cloned()?;
-                    // TODO: support fallback
-                    Ok(name)
-                })
-            });
-        let value = match var {
-            Ok(var) => Ok(RuleValue::ColorRef(var)),
-            Err(_) => parse_color(p).map(RuleValue::Color),
-        }?;
-
-        Ok((name, Rule::Value(value)))
+        self.names.add(&name, p)?;
+        if name.eq_ignore_ascii_case("alpha")
+            || name.eq_ignore_ascii_case("opacity")
+        {
+            return Ok((name, Rule::Alpha(parse_alpha(p)?)));
+        }
+        let (value, source) = parse_rule_value(p)?;
+        Ok((name, Rule::Value(value, source.into())))
     }
 }
 
-impl<'i> AtRuleParser<'i> for RegularRuleParser {
+impl<'i> AtRuleParser<'i> for RegularRuleParser<'i, '_> {
     type Prelude = CowRcStr<'i>;
     type AtRule = (CowRcStr<'i>, Rule<'i>);
     type Error = ParseError<'i>;
@@ -93,6 +208,7 @@ impl<'i> AtRuleParser<'i> for RegularRuleParser {
 
         input.skip_whitespace();
         let ident = input.expect_ident_cloned()?;
+        self.names.add(&ident, input)?;
         Ok(ident)
     }
 
@@ -102,24 +218,33 @@ impl<'i> AtRuleParser<'i> for RegularRuleParser {
         _start: &cssparser::ParserState,
         input: &mut cssparser::Parser<'i, 't>,
     ) -> Result<Self::AtRule, cssparser::ParseError<'i, Self::Error>> {
+        let parser = RegularRuleParser {
+            names: DeclaredNames::new(self.names.duplicates, self.names.strict),
+        };
         let rules: Result<_, _> =
-            DeclarationListParser::new(input, RegularRuleParser).collect();
+            DeclarationListParser::new(input, parser).collect();
         let rules = bail_rule!(rules);
         Ok((prelude, Rule::Nested(rules)))
     }
 }
 
-struct TopLevelParser;
+struct TopLevelParser<'a> {
+    /// The files currently being parsed (the last one is the current file).
+    imports: &'a mut Vec<PathBuf>,
+    options: ParseOptions,
+    /// Names declared more than once in a block (see [`DeclaredNames`]).
+    duplicates: &'a mut Vec<DuplicateDeclaration>,
+}
 
 enum QualifiedType<'i> {
     Root,
     Regular(CowRcStr<'i>),
 }
 
-impl<'i> QualifiedRuleParser<'i> for TopLevelParser {
+impl<'i> QualifiedRuleParser<'i> for TopLevelParser<'_> {
     type Prelude = QualifiedType<'i>;
 
-    type QualifiedRule = TopLevelItem<'i>;
+    type QualifiedRule = Located<'i>;
 
     type Error = ParseError<'i>;
 
@@ -143,33 +268,53 @@ impl<'i> QualifiedRuleParser<'i> for TopLevelParser {
     fn parse_block<'t>(
         &mut self,
         prelude: Self::Prelude,
-        _start: &cssparser::ParserState,
+        start: &cssparser::ParserState,
         input: &mut cssparser::Parser<'i, 't>,
     ) -> Result<Self::QualifiedRule, cssparser::ParseError<'i, Self::Error>>
     {
+        let location = start.source_location();
         match prelude {
             QualifiedType::Root => {
+                let parser = RootBlockParser {
+                    names: DeclaredNames::new(
+                        self.duplicates,
+                        self.options.strict,
+                    ),
+                };
                 let color_map: Result<_, _> =
-                    DeclarationListParser::new(input, RootBlockParser)
-                        .collect();
+                    DeclarationListParser::new(input, parser).collect();
                 let color_map = bail_rule!(color_map);
-                Ok(TopLevelItem::Root(color_map))
+                Ok((location, TopLevelItem::Root(color_map)))
             }
             QualifiedType::Regular(name) => {
+                let parser = RegularRuleParser {
+                    names: DeclaredNames::new(
+                        self.duplicates,
+                        self.options.strict,
+                    ),
+                };
                 let rules: Result<_, _> =
-                    DeclarationListParser::new(input, RegularRuleParser)
-                        .collect();
+                    DeclarationListParser::new(input, parser).collect();
                 let rules = bail_rule!(rules);
-                Ok(TopLevelItem::Regular((name, Rule::Nested(rules))))
+                Ok((
+                    location,
+                    TopLevelItem::Regular((name, Rule::Nested(rules))),
+                ))
             }
         }
     }
 }
 
-impl<'i> AtRuleParser<'i> for TopLevelParser {
-    type Prelude = ();
+enum TopLevelPrelude<'i> {
+    Meta,
+    Import(Box<ThemeParserState<'i>>),
+    Media(ColorScheme),
+}
 
-    type AtRule = TopLevelItem<'i>;
+impl<'i> AtRuleParser<'i> for TopLevelParser<'_> {
+    type Prelude = TopLevelPrelude<'i>;
+
+    type AtRule = Located<'i>;
 
     type Error = ParseError<'i>;
 
@@ -178,43 +323,160 @@ impl<'i> AtRuleParser<'i> for TopLevelParser {
         name: CowRcStr<'i>,
         input: &mut cssparser::Parser<'i, 't>,
     ) -> Result<Self::Prelude, cssparser::ParseError<'i, Self::Error>> {
-        if !name.eq_ignore_ascii_case("chatterino") {
-            return Err(input.new_error(
+        cssparser::match_ignore_ascii_case! { &name,
+            "chatterino" => Ok(TopLevelPrelude::Meta),
+            "import" => {
+                let url = input.expect_url_or_string()?;
+                match import(&url, self.imports, self.options) {
+                    Ok(imported) => Ok(TopLevelPrelude::Import(Box::new(imported))),
+                    Err(e) => Err(input.new_custom_error(e)),
+                }
+            },
+            "media" => {
+                input.expect_parenthesis_block()?;
+                let scheme = input.parse_nested_block(parse_color_scheme)?;
+                Ok(TopLevelPrelude::Media(scheme))
+            },
+            _ => Err(input.new_error(
                 cssparser::BasicParseErrorKind::AtRuleInvalid(name),
-            ));
+            )),
+        }
+    }
+
+    fn rule_without_block(
+        &mut self,
+        prelude: Self::Prelude,
+        start: &cssparser::ParserState,
+    ) -> Result<Self::AtRule, ()> {
+        match prelude {
+            TopLevelPrelude::Import(imported) => {
+                Ok((start.source_location(), TopLevelItem::Import(imported)))
+            }
+            TopLevelPrelude::Meta | TopLevelPrelude::Media(_) => Err(()),
         }
-        Ok(())
     }
 
     fn parse_block<'t>(
         &mut self,
-        _prelude: Self::Prelude,
-        _start: &cssparser::ParserState,
+        prelude: Self::Prelude,
+        start: &cssparser::ParserState,
         input: &mut cssparser::Parser<'i, 't>,
     ) -> Result<Self::AtRule, cssparser::ParseError<'i, Self::Error>> {
+        let location = start.source_location();
+        match prelude {
+            TopLevelPrelude::Meta => (),
+            TopLevelPrelude::Import(_) => {
+                return Err(input.new_error(
+                    cssparser::BasicParseErrorKind::AtRuleBodyInvalid,
+                ))
+            }
+            TopLevelPrelude::Media(scheme) => {
+                return self
+                    .parse_media_block(scheme, input)
+                    .map(|item| (location, item))
+            }
+        }
+
         let mut author = None;
         let mut icon_set = None;
-        for item in DeclarationListParser::new(input, ChatterinoMetaParser) {
+        let mut name = None;
+        let mut version = None;
+        let mut description = None;
+        let mut extras = BTreeMap::new();
+        let mut ignored = vec![];
+        let parser = ChatterinoMetaParser {
+            unknown: self.options.unknown_meta,
+        };
+        for item in DeclarationListParser::new(input, parser) {
             match bail_rule!(item) {
                 ChatterinoMetaItem::Author(v) => author = Some(v),
                 ChatterinoMetaItem::IconSet(v) => icon_set = Some(v),
+                ChatterinoMetaItem::Name(v) => name = Some(v),
+                ChatterinoMetaItem::Version(v) => version = Some(v),
+                ChatterinoMetaItem::Description(v) => description = Some(v),
+                ChatterinoMetaItem::Extra(k, v) => {
+                    extras.insert(k.to_string(), v.to_string());
+                }
+                ChatterinoMetaItem::Ignored(k) => ignored.push(k.to_string()),
             }
         }
 
-        Ok(TopLevelItem::Meta(ChatterinoMeta {
+        let meta = ChatterinoMeta {
             author: author.ok_or_else(|| {
                 input.new_custom_error(ParseError::MissingMetaItem("author"))
             })?,
             icon_set: icon_set.ok_or_else(|| {
                 input.new_custom_error(ParseError::MissingMetaItem("icon-set"))
             })?,
-        }))
+            name,
+            version,
+            description,
+            extras,
+            ignored,
+        };
+        Ok((location, TopLevelItem::Meta(meta)))
+    }
+}
+
+impl<'i> TopLevelParser<'_> {
+    fn parse_media_block<'t>(
+        &mut self,
+        scheme: ColorScheme,
+        input: &mut cssparser::Parser<'i, 't>,
+    ) -> Result<TopLevelItem<'i>, cssparser::ParseError<'i, ParseError<'i>>>
+    {
+        let mut variant = Variant::default();
+        let mut lines = RuleLines::default();
+        let mut root_lines = RuleLines::default();
+
+        let parser = TopLevelParser {
+            imports: &mut *self.imports,
+            options: self.options,
+            duplicates: &mut *self.duplicates,
+        };
+        for item in RuleListParser::new_for_nested_rule(input, parser) {
+            let (location, item) = bail_rule!(item);
+            let line = location.line + 1;
+            let res = match item {
+                TopLevelItem::Root(root) => {
+                    add_root(&mut variant.colors, &mut root_lines, root, line)
+                }
+                TopLevelItem::Regular((name, rule)) => {
+                    add_rule(&mut variant.rules, &mut lines, name, rule, line)
+                }
+                _ => Err(ParseError::UnexpectedInMedia),
+            };
+            if let Err(e) = res {
+                return Err(location.new_custom_error(e));
+            }
+        }
+
+        Ok(TopLevelItem::Media(scheme, variant, lines))
     }
 }
 
-struct RootBlockParser;
-impl<'i> DeclarationParser<'i> for RootBlockParser {
-    type Declaration = (CowRcStr<'i>, RGBA);
+/// Parses the condition of `@media (prefers-color-scheme: <scheme>)`.
+fn parse_color_scheme<'i>(
+    p: &mut cssparser::Parser<'i, '_>,
+) -> Result<ColorScheme, cssparser::ParseError<'i, ParseError<'i>>> {
+    p.expect_ident_matching("prefers-color-scheme")?;
+    p.expect_colon()?;
+    let location = p.current_source_location();
+    let scheme = p.expect_ident()?;
+    cssparser::match_ignore_ascii_case! { scheme,
+        "light" => Ok(ColorScheme::Light),
+        "dark" => Ok(ColorScheme::Dark),
+        _ => Err(location.new_unexpected_token_error(
+            cssparser::Token::Ident(scheme.clone()),
+        )),
+    }
+}
+
+struct RootBlockParser<'i, 'a> {
+    names: DeclaredNames<'i, 'a>,
+}
+impl<'i> DeclarationParser<'i> for RootBlockParser<'i, '_> {
+    type Declaration = (CowRcStr<'i>, RuleValue<'i>);
 
     type Error = ParseError<'i>;
 
@@ -223,22 +485,32 @@ impl<'i> DeclarationParser<'i> for RootBlockParser {
         name: CowRcStr<'i>,
         input: &mut cssparser::Parser<'i, 't>,
     ) -> Result<Self::Declaration, cssparser::ParseError<'i, Self::Error>> {
-        Ok((name, parse_color(input)?))
+        // `name` and `--name` are the same variable
+        self.names.add(&with_var_prefix(&name), input)?;
+        Ok((name, parse_rule_value(input)?.0))
     }
 }
 
-impl<'i> AtRuleParser<'i> for RootBlockParser {
+impl<'i> AtRuleParser<'i> for RootBlockParser<'i, '_> {
     type Prelude = ();
 
-    type AtRule = (CowRcStr<'i>, RGBA);
+    type AtRule = (CowRcStr<'i>, RuleValue<'i>);
 
     type Error = ParseError<'i>;
 }
 
-struct ChatterinoMetaParser;
+struct ChatterinoMetaParser {
+    /// What to do with unknown entries.
+    unknown: UnknownMeta,
+}
 enum ChatterinoMetaItem<'i> {
     Author(CowRcStr<'i>),
     IconSet(CowRcStr<'i>),
+    Name(CowRcStr<'i>),
+    Version(CowRcStr<'i>),
+    Description(CowRcStr<'i>),
+    Extra(CowRcStr<'i>, CowRcStr<'i>),
+    Ignored(CowRcStr<'i>),
 }
 impl<'i> DeclarationParser<'i> for ChatterinoMetaParser {
     type Declaration = ChatterinoMetaItem<'i>;
@@ -252,13 +524,33 @@ impl<'i> DeclarationParser<'i> for ChatterinoMetaParser {
     ) -> Result<Self::Declaration, cssparser::ParseError<'i, Self::Error>> {
         cssparser::match_ignore_ascii_case! { &name,
             "author" => {
-                Ok(ChatterinoMetaItem::Author(p.expect_string_cloned()?))
+                Ok(ChatterinoMetaItem::Author(expect_non_empty(p, "author")?))
             },
             "icon-set" => {
-                Ok(ChatterinoMetaItem::IconSet(p.expect_string_cloned()?))
+                Ok(ChatterinoMetaItem::IconSet(expect_non_empty(p, "icon-set")?))
+            },
+            "name" => {
+                Ok(ChatterinoMetaItem::Name(p.expect_string_cloned()?))
+            },
+            "version" => {
+                Ok(ChatterinoMetaItem::Version(p.expect_string_cloned()?))
             },
-            _ => {
-                Err(p.new_custom_error(ParseError::UnexpectedMeta(name)))
+            "description" => {
+                Ok(ChatterinoMetaItem::Description(p.expect_string_cloned()?))
+            },
+            _ => match self.unknown {
+                UnknownMeta::Keep => {
+                    let value = p.expect_string_cloned()?;
+                    Ok(ChatterinoMetaItem::Extra(name, value))
+                }
+                UnknownMeta::Ignore => {
+                    // the value doesn't matter, but it has to be consumed
+                    while p.next().is_ok() {}
+                    Ok(ChatterinoMetaItem::Ignored(name))
+                }
+                UnknownMeta::Error => {
+                    Err(p.new_custom_error(ParseError::UnexpectedMeta(name)))
+                }
             }
         }
     }
@@ -269,9 +561,94 @@ impl<'i> AtRuleParser<'i> for ChatterinoMetaParser {
     type Error = ParseError<'i>;
 }
 
+fn expect_non_empty<'i>(
+    p: &mut cssparser::Parser<'i, '_>,
+    name: &'static str,
+) -> Result<CowRcStr<'i>, cssparser::ParseError<'i, ParseError<'i>>> {
+    let location = p.current_source_location();
+    let value = p.expect_string_cloned()?;
+    if value.trim().is_empty() {
+        return Err(location.new_custom_error(ParseError::EmptyMetaItem(name)));
+    }
+    Ok(value)
+}
+
+/// Parses either a `var(--name)` or a color, optionally followed by
+/// `!important`, and returns it with the text it was parsed from.
+fn parse_rule_value<'i>(
+    p: &mut cssparser::Parser<'i, '_>,
+) -> Result<(RuleValue<'i>, &'i str), cssparser::ParseError<'i, ParseError<'i>>>
+{
+    p.skip_whitespace();
+    let start = p.position();
+    let value = parse_var_or_color(p, 0)?;
+    let source = p.slice_from(start).trim_end();
+    // there's no cascade, so `!important` doesn't change anything (it's
+    // only accepted for style-sheets copied from elsewhere)
+    p.try_parse(cssparser::parse_important).ok();
+    Ok((value, source))
+}
+
+/// How many `var()`s can be nested as fallbacks.
+const MAX_VAR_DEPTH: usize = 8;
+
+/// Parses a `var(--name)` (with an optional fallback) or a color.
+fn parse_var_or_color<'i>(
+    p: &mut cssparser::Parser<'i, '_>,
+    depth: usize,
+) -> Result<RuleValue<'i>, cssparser::ParseError<'i, ParseError<'i>>> {
+    // errors should point at the value, not the whitespace before it
+    p.skip_whitespace();
+    let location = p.current_source_location();
+    if p.try_parse(|p| p.expect_function_matching("var")).is_err() {
+        return parse_color(p).map(RuleValue::Color);
+    }
+    if depth >= MAX_VAR_DEPTH {
+        return Err(location.new_custom_error(ParseError::VarTooDeep));
+    }
+    p.parse_nested_block(|p| {
+        let name = p.expect_ident_cloned()?;
+        let fallback = match p.try_parse(|p| p.expect_comma()) {
+            Ok(()) => Some(Box::new(parse_var_or_color(p, depth + 1)?)),
+            Err(_) => None,
+        };
+        Ok(RuleValue::ColorRef(name, fallback))
+    })
+}
+
+/// Parses a number or a percentage in `0.0..=1.0`.
+fn parse_alpha<'i>(
+    p: &mut cssparser::Parser<'i, '_>,
+) -> Result<f32, cssparser::ParseError<'i, ParseError<'i>>> {
+    let location = p.current_source_location();
+    let alpha = match p.next()? {
+        Token::Number { value, .. } => *value,
+        Token::Percentage { unit_value, .. } => *unit_value,
+        t => return Err(location.new_unexpected_token_error(t.clone())),
+    };
+    if !(0.0..=1.0).contains(&alpha) {
+        return Err(location.new_custom_error(ParseError::InvalidAlpha));
+    }
+    p.try_parse(cssparser::parse_important).ok();
+    Ok(alpha)
+}
+
 fn parse_color<'i>(
     input: &mut cssparser::Parser<'i, '_>,
 ) -> Result<cssparser::RGBA, cssparser::ParseError<'i, ParseError<'i>>> {
+    // cssparser only reports an unexpected token for these
+    let location = input.current_source_location();
+    let invalid_hex = input.try_parse(|p| match p.next()? {
+        Token::Hash(value) | Token::IDHash(value)
+            if ![3, 4, 6, 8].contains(&value.len()) =>
+        {
+            Ok(value.len())
+        }
+        _ => Err(p.new_error_for_next_token::<()>()),
+    });
+    if let Ok(len) = invalid_hex {
+        return Err(location.new_custom_error(ParseError::InvalidHexColor(len)));
+    }
     match Color::parse(input) {
         Ok(Color::RGBA(color)) => Ok(color),
         Ok(Color::CurrentColor) => {
@@ -286,51 +663,577 @@ struct ThemeParserState<'i> {
     meta: Option<ChatterinoMeta<'i>>,
     colors: Option<CustomColors<'i>>,
     rules: RuleMap<'i>,
+    variants: IndexMap<ColorScheme, Variant<'i>, ahash::RandomState>,
+    /// The line of the `@chatterino` block (or the `@import` it came from).
+    meta_line: Option<u32>,
+    /// The lines of the `:root` blocks that defined the file's own variables
+    /// (imports don't count).
+    root_lines: RuleLines<'i>,
+    /// The lines of the blocks in `rules` (imported ones use the line of the
+    /// `@import`).
+    rule_lines: RuleLines<'i>,
+    /// The lines of the blocks in `variants`.
+    variant_lines: AHashMap<ColorScheme, RuleLines<'i>>,
+    duplicates: Vec<DuplicateDeclaration>,
 }
 
+impl<'i> ThemeParserState<'i> {
+    fn add_meta(
+        &mut self,
+        meta: ChatterinoMeta<'i>,
+        line: u32,
+    ) -> Result<(), ParseError<'i>> {
+        if let Some(first) = self.meta_line {
+            return Err(ParseError::DuplicateMetaBlock(first, line));
+        }
+        self.meta = Some(meta);
+        self.meta_line = Some(line);
+        Ok(())
+    }
+
+    fn add_colors(&mut self, colors: CustomColors<'i>) {
+        self.colors
+            .get_or_insert_with(Default::default)
+            .extend(colors);
+    }
+
+    /// Adds the overrides of a `@media` block (blocks for the same scheme
+    /// are merged).
+    ///
+    /// `line` returns the line a block of the variant was defined on.
+    fn add_variant(
+        &mut self,
+        scheme: ColorScheme,
+        variant: Variant<'i>,
+        line: impl Fn(&CowRcStr<'i>) -> u32,
+    ) -> Result<(), ParseError<'i>> {
+        let existing = self.variants.entry(scheme).or_default();
+        let lines = self.variant_lines.entry(scheme).or_default();
+        existing.colors.extend(variant.colors);
+        for (name, rule) in variant.rules {
+            let line = line(&name);
+            add_rule(&mut existing.rules, lines, name, rule, line)?;
+        }
+        Ok(())
+    }
+
+    /// Adds everything from an imported file (imported at `line`).
+    fn merge(
+        &mut self,
+        imported: Self,
+        line: u32,
+    ) -> Result<(), ParseError<'i>> {
+        if let Some(meta) = imported.meta {
+            self.add_meta(meta, line)?;
+        }
+        if let Some(colors) = imported.colors {
+            self.add_colors(colors);
+        }
+        for (name, rule) in imported.rules {
+            add_rule(&mut self.rules, &mut self.rule_lines, name, rule, line)?;
+        }
+        for (scheme, variant) in imported.variants {
+            self.add_variant(scheme, variant, |_| line)?;
+        }
+        self.duplicates.extend(imported.duplicates);
+        Ok(())
+    }
+
+    fn into_owned<'o>(self) -> ThemeParserState<'o> {
+        ThemeParserState {
+            meta: self.meta.map(ChatterinoMeta::into_owned),
+            colors: self.colors.map(|colors| {
+                colors
+                    .into_iter()
+                    .map(|(k, v)| (owned_str(&k), v.into_owned()))
+                    .collect()
+            }),
+            rules: self
+                .rules
+                .into_iter()
+                .map(|(k, v)| (owned_str(&k), v.into_owned()))
+                .collect(),
+            variants: self
+                .variants
+                .into_iter()
+                .map(|(k, v)| (k, v.into_owned()))
+                .collect(),
+            duplicates: self.duplicates,
+            // imports are reported at the line of the `@import`
+            ..Default::default()
+        }
+    }
+}
+
+/// Adds the variables of a `:root` block at `line` (`lines` has the lines of
+/// the previous `:root` blocks' variables).
+///
+/// Multiple blocks are merged, but a variable must only be defined once.
+fn add_root<'i>(
+    colors: &mut CustomColors<'i>,
+    lines: &mut RuleLines<'i>,
+    root: CustomColors<'i>,
+    line: u32,
+) -> Result<(), ParseError<'i>> {
+    for (name, value) in root {
+        if let Some(first) = lines.insert(with_var_prefix(&name), line) {
+            return Err(ParseError::DuplicateVariable(name, first, line));
+        }
+        colors.insert(name, value);
+    }
+    Ok(())
+}
+
+/// Adds the block `name` defined at `line` (`lines` has the lines of the
+/// blocks in `rules`).
+fn add_rule<'i>(
+    rules: &mut RuleMap<'i>,
+    lines: &mut RuleLines<'i>,
+    name: CowRcStr<'i>,
+    rule: Rule<'i>,
+    line: u32,
+) -> Result<(), ParseError<'i>> {
+    match rules.entry(name) {
+        indexmap::map::Entry::Vacant(e) => {
+            lines.insert(e.key().clone(), line);
+            e.insert(rule);
+            Ok(())
+        }
+        indexmap::map::Entry::Occupied(e) => Err(ParseError::DuplicateBlock(
+            e.key().clone(),
+            lines[e.key()],
+            line,
+        )),
+    }
+}
+
+/// Parses a style-sheet.
+///
+/// `file` is the path of the style-sheet. Relative imports are resolved
+/// relative to it (or to the working directory if it's `None`).
+///
+/// Unknown entries of `@chatterino` and duplicate declarations are handled
+/// according to `options`.
+#[cfg(test)]
 pub fn parse<'i>(
     input: &mut cssparser::Parser<'i, '_>,
+    file: Option<&Path>,
+    options: ParseOptions,
 ) -> Result<Theme<'i>, cssparser::ParseError<'i, ParseError<'i>>> {
+    match parse_recovering(input, file, options) {
+        (Some(theme), errors) if errors.is_empty() => Ok(theme),
+        (_, mut errors) => Err(errors.swap_remove(0)),
+    }
+}
+
+/// Like [`parse`], but continues after top level blocks that fail to parse.
+///
+/// Returns the theme made of the remaining blocks (`None` if there's no
+/// `@chatterino` block) and all errors in the order they occurred.
+pub fn parse_recovering<'i>(
+    input: &mut cssparser::Parser<'i, '_>,
+    file: Option<&Path>,
+    options: ParseOptions,
+) -> (
+    Option<Theme<'i>>,
+    Vec<cssparser::ParseError<'i, ParseError<'i>>>,
+) {
+    let mut imports = Vec::new();
+    if let Some(file) = file {
+        imports
+            .push(fs::canonicalize(file).unwrap_or_else(|_| file.to_owned()));
+    }
+    let mut errors = vec![];
+    let state = parse_state(input, &mut imports, options, &mut errors);
+
+    let Some(meta) = state.meta else {
+        errors.push(input.new_custom_error(ParseError::MissingMetaBlock));
+        return (None, errors);
+    };
+    let theme = Theme {
+        meta,
+        colors: state.colors.unwrap_or_default(),
+        rules: state.rules,
+        variants: state.variants,
+        duplicates: state.duplicates,
+    };
+    (Some(theme), errors)
+}
+
+/// Parses the top level items, pushing the errors of the ones that couldn't
+/// be parsed (or added) to `errors`.
+fn parse_state<'i>(
+    input: &mut cssparser::Parser<'i, '_>,
+    imports: &mut Vec<PathBuf>,
+    options: ParseOptions,
+    errors: &mut Vec<cssparser::ParseError<'i, ParseError<'i>>>,
+) -> ThemeParserState<'i> {
     let mut state = ThemeParserState::default();
 
-    for item in RuleListParser::new_for_stylesheet(input, TopLevelParser) {
-        match bail_rule!(item) {
-            TopLevelItem::Meta(meta) if state.meta.is_none() => {
-                state.meta = Some(meta);
-            }
-            TopLevelItem::Meta(_) => {
-                return Err(
-                    input.new_custom_error(ParseError::DuplicateMetaBlock)
-                );
-            }
-            TopLevelItem::Root(root) if state.colors.is_none() => {
-                state.colors = Some(root);
+    let mut duplicates = vec![];
+    let parser = TopLevelParser {
+        imports,
+        options,
+        duplicates: &mut duplicates,
+    };
+    for item in RuleListParser::new_for_stylesheet(input, parser) {
+        let (location, item) = match item {
+            Ok(item) => item,
+            Err((e, _)) => {
+                errors.push(e);
+                continue;
             }
-            TopLevelItem::Root(_) => {
-                return Err(
-                    input.new_custom_error(ParseError::DuplicateRootBlock)
-                );
+        };
+        let line = location.line + 1;
+        let res = match item {
+            TopLevelItem::Meta(meta) => state.add_meta(meta, line),
+            TopLevelItem::Root(root) => add_root(
+                state.colors.get_or_insert_with(Default::default),
+                &mut state.root_lines,
+                root,
+                line,
+            ),
+            TopLevelItem::Regular((name, rule)) => add_rule(
+                &mut state.rules,
+                &mut state.rule_lines,
+                name,
+                rule,
+                line,
+            ),
+            TopLevelItem::Import(imported) => state.merge(*imported, line),
+            TopLevelItem::Media(scheme, variant, lines) => {
+                state.add_variant(scheme, variant, |name| lines[name])
             }
-            TopLevelItem::Regular((name, rule)) => {
-                match state.rules.entry(name) {
-                    hash_map::Entry::Vacant(e) => {
-                        e.insert(rule);
-                    }
-                    hash_map::Entry::Occupied(e) => {
-                        return Err(input.new_custom_error(
-                            ParseError::DuplicateBlock(e.key().clone()),
-                        ));
-                    }
+        };
+        if let Err(e) = res {
+            errors.push(location.new_custom_error(e));
+        }
+    }
+    // after the ones of the imports (added while merging)
+    state.duplicates.append(&mut duplicates);
+
+    state
+}
+
+fn import<'o>(
+    url: &str,
+    imports: &mut Vec<PathBuf>,
+    options: ParseOptions,
+) -> Result<ThemeParserState<'o>, ParseError<'o>> {
+    let failed = |e: &dyn std::fmt::Display| {
+        ParseError::ImportFailed(url.to_owned(), e.to_string())
+    };
+
+    let path = match imports.last().and_then(|f| f.parent()) {
+        Some(dir) => dir.join(url),
+        None => PathBuf::from(url),
+    };
+    let path = fs::canonicalize(path).map_err(|e| failed(&e))?;
+    if imports.contains(&path) {
+        let chain = imports
+            .iter()
+            .chain(std::iter::once(&path))
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>();
+        return Err(ParseError::ImportCycle(chain.join(" -> ")));
+    }
+    let source = super::read_source(&path).map_err(|e| failed(&e))?;
+
+    imports.push(path);
+    let mut parser_input = ParserInput::new(&source);
+    let mut parser = cssparser::Parser::new(&mut parser_input);
+    let mut parse_errors = vec![];
+    let mut state =
+        parse_state(&mut parser, imports, options, &mut parse_errors);
+    let path = imports.pop();
+    for duplicate in &mut state.duplicates {
+        // nested imports already have their file
+        if duplicate.file.is_none() {
+            duplicate.file = path.clone();
+        }
+    }
+
+    // only the first error is reported for imports
+    match parse_errors.first() {
+        None => Ok(state.into_owned()),
+        Some(e) => Err(failed(&format_args!(
+            "[line {}, column {}] {}",
+            e.location.line + 1,
+            e.location.column,
+            errors::format_css_parse_error(e)
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cssparser::ParseErrorKind;
+
+    use super::*;
+
+    const META: &str = r#"@chatterino { author: "a"; icon-set: "dark"; }"#;
+
+    /// Parses `body` (after a metadata block) and returns the hex digit
+    /// counts of the `InvalidHexColor` errors and their columns.
+    fn invalid_hex_colors(body: &str) -> Vec<(usize, u32)> {
+        let source = format!("{META}\n{body}");
+        let mut input = ParserInput::new(&source);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let (_, errors) =
+            parse_recovering(&mut parser, None, ParseOptions::STRICT);
+        errors
+            .iter()
+            .map(|e| match e.kind {
+                ParseErrorKind::Custom(ParseError::InvalidHexColor(len)) => {
+                    (len, e.location.column)
                 }
-            }
+                ref kind => panic!("unexpected error: {kind:?}"),
+            })
+            .collect()
+    }
+
+    /// Parses and flattens `body` (after a metadata block).
+    fn flatten(body: &str) -> AHashMap<String, cssparser::RGBA> {
+        let source = format!("{META}\n{body}");
+        let mut input = ParserInput::new(&source);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let theme = parse(&mut parser, None, ParseOptions::STRICT).unwrap();
+        let (flat, replaced) = theme.flatten(".", false, None).unwrap();
+        assert!(replaced.is_empty());
+        flat.rules
+    }
+
+    /// The extras and ignored entries of a `@chatterino` block.
+    type MetaEntries = (Vec<(String, String)>, Vec<String>);
+
+    /// Parses `meta` (the entries of a `@chatterino` block).
+    fn parse_meta(
+        meta: &str,
+        unknown: UnknownMeta,
+    ) -> Result<MetaEntries, String> {
+        let source = format!("@chatterino {{ {meta} }}");
+        let mut input = ParserInput::new(&source);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let options = ParseOptions {
+            unknown_meta: unknown,
+            strict: true,
         };
+        parse(&mut parser, None, options)
+            .map(|theme| {
+                (theme.meta.extras.into_iter().collect(), theme.meta.ignored)
+            })
+            .map_err(|e| errors::format_css_parse_error(&e))
     }
 
-    Ok(Theme {
-        meta: state.meta.ok_or_else(|| {
-            input.new_custom_error(ParseError::MissingMetaBlock)
-        })?,
-        colors: state.colors.unwrap_or_default(),
-        rules: state.rules,
-    })
+    #[test]
+    fn unknown_meta() {
+        let meta = r#"author: "a"; icon-set: "dark"; tags: "x y";"#;
+        assert_eq!(
+            parse_meta(meta, UnknownMeta::Ignore),
+            Ok((vec![], vec!["tags".to_owned()]))
+        );
+        assert_eq!(
+            parse_meta(meta, UnknownMeta::Keep),
+            Ok((vec![("tags".to_owned(), "x y".to_owned())], vec![]))
+        );
+        assert_eq!(
+            parse_meta(meta, UnknownMeta::Error),
+            Err("Unexpected tags".to_owned())
+        );
+
+        // ignored entries can have any value
+        let meta = r#"author: "a"; icon-set: "dark"; a: 1 2; b: var(--c);"#;
+        assert_eq!(
+            parse_meta(meta, UnknownMeta::Ignore),
+            Ok((vec![], vec!["a".to_owned(), "b".to_owned()]))
+        );
+        assert!(parse_meta(meta, UnknownMeta::Keep).is_err());
+    }
+
+    #[test]
+    fn important_is_ignored() {
+        let rules = flatten(
+            ":root { --a: #000 !important; }
+            a { color: #fff !important; b: var(--a) ! important; }",
+        );
+        assert_eq!(rules["a.color"], cssparser::RGBA::new(255, 255, 255, 255));
+        assert_eq!(rules["a.b"], cssparser::RGBA::new(0, 0, 0, 255));
+    }
+
+    #[test]
+    fn eight_digit_hex_keeps_alpha() {
+        let rules = flatten(
+            ":root { --a: #11223344; }
+            a { b: #55667788; c: var(--a); }",
+        );
+        assert_eq!(rules["a.b"], cssparser::RGBA::new(0x55, 0x66, 0x77, 0x88));
+        assert_eq!(rules["a.c"], cssparser::RGBA::new(0x11, 0x22, 0x33, 0x44));
+    }
+
+    #[test]
+    fn var_with_fallback() {
+        let rules = flatten(
+            ":root { --accent: #123456; --b: var(--missing, #abcdef); }
+            a {
+                present: var(--accent, #3498db);
+                missing: var(--other, #3498db);
+                nested: var(--missing, var(--accent, #000));
+                root: var(--b);
+            }",
+        );
+        assert_eq!(
+            rules["a.present"],
+            cssparser::RGBA::new(0x12, 0x34, 0x56, 255)
+        );
+        assert_eq!(
+            rules["a.missing"],
+            cssparser::RGBA::new(0x34, 0x98, 0xdb, 255)
+        );
+        assert_eq!(
+            rules["a.nested"],
+            cssparser::RGBA::new(0x12, 0x34, 0x56, 255)
+        );
+        assert_eq!(
+            rules["a.root"],
+            cssparser::RGBA::new(0xab, 0xcd, 0xef, 255)
+        );
+    }
+
+    #[test]
+    fn color_functions_and_names() {
+        let rules = flatten(
+            "a {
+                hsl: hsl(210 50% 40%);
+                hsla: hsla(210, 50%, 40%, 0.25);
+                rgba: rgba(0, 0, 0, 0.5);
+                rgb: rgb(10 20 30 / 50%);
+                name: rebeccapurple;
+            }",
+        );
+        assert_eq!(rules["a.hsl"], cssparser::RGBA::new(0x33, 0x66, 0x99, 255));
+        assert_eq!(rules["a.hsla"], cssparser::RGBA::new(0x33, 0x66, 0x99, 64));
+        assert_eq!(rules["a.rgba"], cssparser::RGBA::new(0, 0, 0, 128));
+        assert_eq!(rules["a.rgb"], cssparser::RGBA::new(10, 20, 30, 128));
+        assert_eq!(
+            rules["a.name"],
+            cssparser::RGBA::new(0x66, 0x33, 0x99, 255)
+        );
+    }
+
+    #[test]
+    fn transparent() {
+        let rules = flatten(
+            ":root { --a: transparent; }
+            a { b: transparent; c: var(--a); }",
+        );
+        assert_eq!(rules["a.b"], cssparser::RGBA::new(0, 0, 0, 0));
+        assert_eq!(rules["a.c"], cssparser::RGBA::new(0, 0, 0, 0));
+    }
+
+    #[test]
+    fn duplicate_declarations() {
+        let source = format!(
+            "{META}
+a {{
+  b: #111;
+  @nest c {{ d: #222; d: #333; }}
+  b: #444;
+}}"
+        );
+        let mut input = ParserInput::new(&source);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let theme = parse(&mut parser, None, ParseOptions::default()).unwrap();
+        let duplicates = theme
+            .duplicates
+            .iter()
+            .map(|d| (d.name.as_str(), d.file.is_none(), d.line, d.column))
+            .collect::<Vec<_>>();
+        assert_eq!(duplicates, [("d", true, 4, 25), ("b", true, 5, 6)]);
+        // the later value wins
+        let (flat, _) = theme.flatten(".", false, None).unwrap();
+        assert_eq!(
+            flat.rules["a.b"],
+            cssparser::RGBA::new(0x44, 0x44, 0x44, 255)
+        );
+        assert_eq!(
+            flat.rules["a.c.d"],
+            cssparser::RGBA::new(0x33, 0x33, 0x33, 255)
+        );
+
+        let mut input = ParserInput::new(&source);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let (_, errors) =
+            parse_recovering(&mut parser, None, ParseOptions::STRICT);
+        let errors = errors
+            .iter()
+            .map(|e| (errors::format_css_parse_error(e), e.location.line + 1))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [("Found duplicate declaration ('d')".to_owned(), 4)]
+        );
+    }
+
+    #[test]
+    fn root_with_and_without_prefix() {
+        let parse_errors = |body: &str| {
+            let source = format!("{META}\n{body}");
+            let mut input = ParserInput::new(&source);
+            let mut parser = cssparser::Parser::new(&mut input);
+            let (_, errors) =
+                parse_recovering(&mut parser, None, ParseOptions::STRICT);
+            errors
+                .into_iter()
+                .map(|e| match e.kind {
+                    ParseErrorKind::Custom(e) => e.to_string(),
+                    kind => panic!("unexpected error: {kind:?}"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            parse_errors(":root { a: #fff; --a: #000; }"),
+            ["Found duplicate declaration ('--a')"]
+        );
+        assert_eq!(
+            parse_errors(":root { --a: #fff; }\n:root { a: #000; }"),
+            ["'a' is already defined in the :root block at line 2 (redefined at line 3)"]
+        );
+    }
+
+    #[test]
+    fn valid_hex_colors() {
+        assert!(invalid_hex_colors(
+            "a { b: #fff; c: #ffff; d: #ffffff; e: #ffffffff; f: #123; }"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn invalid_hex_color_lengths() {
+        for len in [1, 2, 5, 7, 9] {
+            let value = "a".repeat(len);
+            assert_eq!(
+                invalid_hex_colors(&format!("a {{ b: #{value}; }}")),
+                [(len, 8)],
+                "{len} digits"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_hex_color_starting_with_digit() {
+        // `#1..` is a `Hash` token, `#a..` an `IDHash`
+        assert_eq!(invalid_hex_colors("a { b: #12345; }"), [(5, 8)]);
+    }
+
+    #[test]
+    fn invalid_hex_color_in_root_and_fallback() {
+        assert_eq!(
+            invalid_hex_colors(
+                ":root { --a: #fffff; }\na { b: var(--a, #ff); }"
+            ),
+            [(5, 14), (2, 17)]
+        );
+    }
 }
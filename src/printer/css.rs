@@ -0,0 +1,103 @@
+use std::{collections::BTreeMap, io};
+
+use cssparser::RGBA;
+
+use crate::model::FlatTheme;
+
+use super::Printer;
+
+enum Node<'a> {
+    Color(RGBA),
+    Block(BTreeMap<&'a str, Node<'a>>),
+}
+
+/// Writes `theme` as a style-sheet (with one block per top level name).
+///
+/// Every path needs at least two parts and mustn't be both a color and
+/// a block.
+pub fn generate(
+    p: &mut Printer<impl io::Write>,
+    theme: &FlatTheme,
+) -> io::Result<()> {
+    let meta = &theme.meta;
+    let optional = [
+        ("name", &meta.name),
+        ("version", &meta.version),
+        ("description", &meta.description),
+    ];
+    p.block("@chatterino {", "}", |p| {
+        writeln!(p, "author: {};", quote(&meta.author))?;
+        writeln!(p, "icon-set: {};", quote(&meta.icon_set))?;
+        for (key, value) in optional {
+            if let Some(value) = value {
+                writeln!(p, "{key}: {};", quote(value))?;
+            }
+        }
+        for (key, value) in &meta.extras {
+            writeln!(p, "{key}: {};", quote(value))?;
+        }
+        Ok(())
+    })?;
+
+    let mut root = BTreeMap::new();
+    for (path, color) in &theme.rules {
+        let mut parts = path.split('.').peekable();
+        let mut block = &mut root;
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                block.insert(part, Node::Color(*color));
+                break;
+            }
+            let node = block
+                .entry(part)
+                .or_insert_with(|| Node::Block(BTreeMap::new()));
+            let Node::Block(inner) = node else {
+                panic!("{path} is both a color and a block");
+            };
+            block = inner;
+        }
+    }
+
+    for (name, node) in &root {
+        let Node::Block(block) = node else {
+            panic!("Top level item not a block");
+        };
+        writeln!(p)?;
+        p.block(&format!("{name} {{"), "}", |p| write_block(p, block))?;
+    }
+    Ok(())
+}
+
+fn write_block(
+    p: &mut Printer<impl io::Write>,
+    block: &BTreeMap<&str, Node>,
+) -> io::Result<()> {
+    for (name, node) in block {
+        match node {
+            Node::Color(RGBA {
+                red,
+                green,
+                blue,
+                alpha,
+            }) => {
+                let alpha = match alpha {
+                    255 => String::new(),
+                    a => format!("{a:02x}"),
+                };
+                writeln!(p, "{name}: #{red:02x}{green:02x}{blue:02x}{alpha};")?;
+            }
+            Node::Block(inner) => {
+                p.block(&format!("@nest {name} {{"), "}", |p| {
+                    write_block(p, inner)
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn quote(s: &str) -> String {
+    let mut quoted = String::new();
+    cssparser::serialize_string(s, &mut quoted).ok();
+    quoted
+}
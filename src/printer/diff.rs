@@ -0,0 +1,31 @@
+use std::io;
+
+use crate::model::FlatTheme;
+
+use super::{ChannelOrder, Printer};
+
+/// Prints the paths that were added (`+`), removed (`-`), or changed (`~`)
+/// from `old` to `new`, sorted by path.
+pub fn generate(
+    p: &mut Printer<impl io::Write>,
+    old: &FlatTheme,
+    new: &FlatTheme,
+) -> io::Result<()> {
+    let hex = |color| ChannelOrder::AlphaFirst.format(color);
+    let mut paths =
+        old.rules.keys().chain(new.rules.keys()).collect::<Vec<_>>();
+    paths.sort_unstable();
+    paths.dedup();
+
+    for path in paths {
+        match (old.rules.get(path), new.rules.get(path)) {
+            (Some(old), Some(new)) if old != new => {
+                writeln!(p, "~ {path} {} -> {}", hex(old), hex(new))?
+            }
+            (Some(old), None) => writeln!(p, "- {path} {}", hex(old))?,
+            (None, Some(new)) => writeln!(p, "+ {path} {}", hex(new))?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
@@ -1,51 +1,111 @@
 use std::io;
 
-use crate::layout::{Layout, LayoutItem};
+use crate::layout::{self, FlatLayoutItem, Layout, LayoutItem};
 
-use super::Printer;
+use super::{CodeOptions, Printer};
 
 pub fn generate_header(
     p: &mut Printer<impl io::Write>,
     layout: &Layout,
+    flat_layout: &[FlatLayoutItem],
+    options: &CodeOptions,
 ) -> io::Result<()> {
+    let class = &options.class_name;
     p.write_line("#include <QColor>")?;
     p.write_line("#include <QByteArray>")?;
+    p.write_line("#include <QString>")?;
+    if options.qml {
+        p.write_line("#include <QObject>")?;
+    }
+    if options.all_colors {
+        p.write_line("#include <QMap>")?;
+    }
+    p.write_line("#include <cstddef>")?;
     p.write_line("")?;
 
     p.write_line("namespace chatterino::theme {")?;
 
-    p.write_line("class GeneratedTheme {")?;
-    p.write_line("public:")?;
-    p.indent();
+    if options.qml {
+        let open = format!("class {class} : public QObject {{");
+        p.block(&open, "", |p| {
+            p.write_line("Q_OBJECT")?;
+            write_properties(p, flat_layout)
+        })?;
+        writeln!(p)?;
+    } else {
+        writeln!(p, "class {class} {{")?;
+    }
+    p.block("public:", "", |p| {
+        write_version(p)?;
+        write_theme_keys(p, flat_layout, options.preserve_case)?;
 
-    for (name, def) in layout.definitions.iter() {
-        writeln!(p, "struct {name} {{")?;
-        p.indent();
-        for item in def.fields.iter() {
-            write_struct_field(p, item)?;
+        for (name, def) in layout.definitions.iter() {
+            write_doc(p, def.doc.as_deref())?;
+            p.block(&format!("struct {name} {{"), "};", |p| {
+                for item in def.fields.iter() {
+                    write_struct_field(p, item)?;
+                }
+                if options.eq_operators {
+                    write_eq_operators(p, name, &def.fields)?;
+                }
+                Ok(())
+            })?;
         }
-        p.dedent();
-        writeln!(p, "}};")?;
-    }
 
-    for (name, fields) in layout.items.iter() {
-        write_struct(p, name, fields)?;
-    }
+        for (name, def) in layout.items.iter() {
+            write_struct(p, name, def.doc.as_deref(), &def.fields)?;
+        }
 
-    writeln!(p, "GeneratedTheme();")?;
-    p.dedent();
+        writeln!(p, "{class}();")?;
+        if options.loader {
+            // reads a c2theme, so colors can be changed without recompiling
+            writeln!(p, "bool loadTheme(const QString &path);")?;
+        }
+        if options.all_colors {
+            // for editors that list every color
+            writeln!(p, "QMap<QByteArray, QColor> getAllColors() const;")?;
+        }
+        if options.eq_operators {
+            writeln!(p)?;
+            writeln!(p, "bool operator==(const {class} &other) const;")?;
+            writeln!(p, "bool operator!=(const {class} &other) const;")?;
+        }
+        if options.color_names || options.emit_keys {
+            writeln!(p)?;
+            // preprocessor directives aren't indented
+            if !options.emit_keys {
+                p.write("#ifndef NDEBUG\n")?;
+            }
+            writeln!(
+                p,
+                "static const char *const colorNames[{}];",
+                layout::id_count(flat_layout)
+            )?;
+            if !options.emit_keys {
+                p.write("#endif\n")?;
+            }
+        }
+        if options.qml {
+            write_getters(p, flat_layout)?;
+        }
+        Ok(())
+    })?;
+    if options.qml {
+        writeln!(p)?;
+        p.block("signals:", "", |p| writeln!(p, "void colorsChanged();"))?;
+    }
     writeln!(p)?;
-    writeln!(p, "protected:")?;
-    p.indent();
-    writeln!(p, "bool setColor(const QByteArray &name, QColor color);")?;
-    writeln!(p, "void reset();")?;
-    writeln!(p, "void applyChanges();")?;
-    p.dedent();
+    p.block("protected:", "", |p| {
+        writeln!(p, "bool setColor(const QByteArray &name, QColor color);")?;
+        writeln!(p, "bool setColor(QLatin1String name, QColor color);")?;
+        writeln!(p, "void setColor(ThemeKey key, QColor color);")?;
+        writeln!(p, "void reset();")?;
+        writeln!(p, "void applyChanges();")
+    })?;
     writeln!(p)?;
-    writeln!(p, "private:")?;
-    p.indent();
-    writeln!(p, "QColor colors_[{}];", layout.count_items())?;
-    p.dedent();
+    p.block("private:", "", |p| {
+        writeln!(p, "QColor colors_[{}];", layout::id_count(flat_layout))
+    })?;
 
     p.write_line("};")?;
     p.write_line("}  // namespace chatterino::theme")?;
@@ -53,6 +113,25 @@ pub fn generate_header(
     Ok(())
 }
 
+/// Writes the version of cstylegen as a number (`major * 10000 + minor * 100 +
+/// patch`) and as a string, so users can `static_assert` on it.
+fn write_version(p: &mut Printer<impl io::Write>) -> io::Result<()> {
+    let number = [
+        env!("CARGO_PKG_VERSION_MAJOR"),
+        env!("CARGO_PKG_VERSION_MINOR"),
+        env!("CARGO_PKG_VERSION_PATCH"),
+    ]
+    .iter()
+    .map(|part| part.parse::<u32>().expect("Cargo versions are numeric"))
+    .fold(0, |acc, part| acc * 100 + part);
+    writeln!(p, "static constexpr int GENERATOR_VERSION = {number};")?;
+    writeln!(
+        p,
+        "static constexpr const char *GENERATOR_VERSION_STRING = \"{}\";",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
 fn write_struct_field(
     p: &mut Printer<impl io::Write>,
     field: &LayoutItem,
@@ -65,27 +144,164 @@ fn write_struct_field(
         } => {
             writeln!(p, "{referenced} {field_name};")
         }
-        LayoutItem::Field { name } => {
+        LayoutItem::Field { name, .. } => {
             writeln!(p, "QColor {name};")
         }
         LayoutItem::Struct {
             field_name, fields, ..
-        } => write_struct(p, field_name, fields),
+        } => write_struct(p, field_name, None, fields),
     }
 }
 
 fn write_struct(
     p: &mut Printer<impl io::Write>,
     struct_name: &str,
+    doc: Option<&str>,
     fields: &[LayoutItem],
 ) -> io::Result<()> {
     writeln!(p)?;
-    writeln!(p, "struct {{")?;
-    p.indent();
+    write_doc(p, doc)?;
+    p.block("struct {", &format!("}} {struct_name};"), |p| {
+        for item in fields {
+            write_struct_field(p, item)?;
+        }
+        Ok(())
+    })
+}
+
+/// Writes `doc` as a `/** .. */` comment.
+fn write_doc(
+    p: &mut Printer<impl io::Write>,
+    doc: Option<&str>,
+) -> io::Result<()> {
+    let Some(doc) = doc.map(str::trim_end) else {
+        return Ok(());
+    };
+    // a `*/` would end the comment early
+    let doc = doc.replace("*/", "* /");
+    if !doc.contains('\n') {
+        return writeln!(p, "/** {doc} */");
+    }
+    writeln!(p, "/**")?;
+    for line in doc.lines() {
+        match line.trim_end() {
+            "" => writeln!(p, " *")?,
+            line => writeln!(p, " * {line}")?,
+        }
+    }
+    writeln!(p, " */")
+}
+
+/// Writes inline comparison operators for the named struct `name`.
+fn write_eq_operators(
+    p: &mut Printer<impl io::Write>,
+    name: &str,
+    fields: &[LayoutItem],
+) -> io::Result<()> {
+    // referenced definitions have their own operators, so they're compared
+    // as a whole
+    fn collect(members: &mut Vec<String>, prefix: &str, item: &LayoutItem) {
+        // these are C++ members, so the names are used as-is
+        let path = match prefix {
+            "" => item.name().to_owned(),
+            _ => format!("{prefix}.{}", item.name()),
+        };
+        match item {
+            LayoutItem::Struct { fields, .. } => {
+                for field in fields {
+                    collect(members, &path, field);
+                }
+            }
+            _ => members.push(path),
+        }
+    }
+
+    let mut members = vec![];
     for item in fields {
-        write_struct_field(p, item)?;
+        collect(&mut members, "", item);
+    }
+
+    writeln!(p)?;
+    let open = format!("bool operator==(const {name} &other) const {{");
+    p.block(&open, "}", |p| {
+        for member in &members {
+            writeln!(p, "if (this->{member} != other.{member}) return false;")?;
+        }
+        writeln!(p, "return true;")
+    })?;
+    writeln!(
+        p,
+        "bool operator!=(const {name} &other) const {{ return !(*this == other); }}"
+    )
+}
+
+fn write_theme_keys(
+    p: &mut Printer<impl io::Write>,
+    flat_layout: &[FlatLayoutItem],
+    preserve_case: bool,
+) -> io::Result<()> {
+    p.block("enum class ThemeKey : size_t {", "};", |p| {
+        for (key, id) in layout::keys(flat_layout, "_", preserve_case) {
+            writeln!(p, "{key} = {id},")?;
+        }
+        Ok(())
+    })
+}
+
+/// Calls `f` with the C++ member path (`tabs.regular.text`) and the property
+/// name (`tabs_regular_text`) of every field.
+pub(super) fn for_each_field<W>(
+    p: &mut Printer<W>,
+    flat_layout: &[FlatLayoutItem],
+    mut f: impl FnMut(&mut Printer<W>, &str, &str) -> io::Result<()>,
+) -> io::Result<()> {
+    fn visit<W>(
+        p: &mut Printer<W>,
+        path: &mut Vec<String>,
+        item: &FlatLayoutItem,
+        f: &mut impl FnMut(&mut Printer<W>, &str, &str) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let name = item.name();
+        path.push(name.to_string());
+        match item {
+            FlatLayoutItem::Field { .. } => {
+                f(p, &path.join("."), &path.join("_"))?
+            }
+            FlatLayoutItem::Struct { fields, .. } => {
+                for field in fields {
+                    visit(p, path, field, f)?;
+                }
+            }
+        }
+        path.pop();
+        Ok(())
+    }
+
+    let mut path = vec![];
+    for item in flat_layout {
+        visit(p, &mut path, item, &mut f)?;
     }
-    p.dedent();
-    writeln!(p, "}} {struct_name};")?;
     Ok(())
 }
+
+fn write_properties(
+    p: &mut Printer<impl io::Write>,
+    flat_layout: &[FlatLayoutItem],
+) -> io::Result<()> {
+    for_each_field(p, flat_layout, |p, _, property| {
+        writeln!(
+            p,
+            "Q_PROPERTY(QColor {property} READ {property} NOTIFY colorsChanged)"
+        )
+    })
+}
+
+fn write_getters(
+    p: &mut Printer<impl io::Write>,
+    flat_layout: &[FlatLayoutItem],
+) -> io::Result<()> {
+    writeln!(p)?;
+    for_each_field(p, flat_layout, |p, member, property| {
+        writeln!(p, "QColor {property}() const {{ return this->{member}; }}")
+    })
+}
@@ -1,160 +1,554 @@
 use std::io;
 
+use cssparser::RGBA;
+
 use crate::{
-    combinator::combine_path,
-    layout::{FlatLayoutItem, Layout},
+    combinator::{combine_path, DEFAULT_SEPARATOR},
+    layout::{self, FlatLayoutItem},
     model::FlatTheme,
 };
 
-use super::Printer;
+use super::{header::for_each_field, phf, CodeOptions, Matcher, Printer};
 
 pub fn generate_impl(
     p: &mut Printer<impl io::Write>,
-    layout: &Layout,
+    header_name: &str,
+    flat_layout: &[FlatLayoutItem],
     theme: &FlatTheme,
+    options: &CodeOptions,
 ) -> io::Result<()> {
+    let class = &options.class_name;
     // TODO: should this be a template?
-    p.write_line("#include \"GeneratedTheme.hpp\"")?;
-    p.write_line("#include <QColor>")?;
-    p.write_line("#include <QString>")?;
-    p.write_line("#include <QByteArray>")?;
-    p.write_line("#include <QMap>")?;
-    p.write_line("#include <cstring>")?;
+    writeln!(p, "#include \"{header_name}\"")?;
+    if options.default_includes {
+        p.write_line("#include <QColor>")?;
+        p.write_line("#include <QString>")?;
+        p.write_line("#include <QByteArray>")?;
+        p.write_line("#include <QMap>")?;
+        if options.loader {
+            p.write_line("#include <QFile>")?;
+        }
+        p.write_line("#include <cstring>")?;
+        if options.matcher == Matcher::Phf {
+            p.write_line("#include <cstdint>")?;
+        }
+    }
+    for include in &options.includes {
+        // `<..>` and `".."` are kept, plain paths are quoted
+        if include.starts_with('<') || include.starts_with('"') {
+            writeln!(p, "#include {include}")?;
+        } else {
+            writeln!(p, "#include \"{include}\"")?;
+        }
+    }
     p.write_line("")?;
 
-    p.write_line("namespace {")?;
-    p.indent();
-    p.write_line("int getDataIndex(const QByteArray &name);")?;
-    p.dedent();
-    p.write_line("} //  namespace")?;
+    p.block("namespace {", "} //  namespace", |p| {
+        p.write_line("int getDataIndex(QLatin1String name);")
+    })?;
 
     p.write_line("namespace chatterino::theme {")?;
 
-    p.write_line("GeneratedTheme::GeneratedTheme() {")?;
-    p.indent();
+    // the same keys are used for `ThemeKey`, so these can't get out of sync
+    let keys =
+        layout::keys(flat_layout, DEFAULT_SEPARATOR, options.preserve_case);
 
-    p.write_line("this->reset();")?;
-    p.write_line("this->applyChanges();")?;
+    p.block(&format!("{class}::{class}() {{"), "}", |p| {
+        // the header sizes `colors_` separately from the ids used here
+        writeln!(
+            p,
+            "static_assert(sizeof(colors_) / sizeof(colors_[0]) == {}, \"the header and the implementation were generated from different layouts\");",
+            layout::id_count(flat_layout)
+        )?;
+        p.write_line("this->reset();")?;
+        p.write_line("this->applyChanges();")
+    })?;
 
-    p.dedent();
-    p.write_line("}")?;
+    p.block(&format!("void {class}::applyChanges() {{"), "}", |p| {
+        p.write_line("const auto d = [this](size_t i) -> const QColor& { return this->colors_[i]; };")?;
 
-    p.write_line("void GeneratedTheme::applyChanges() {")?;
-    p.indent();
-    p.write_line("const auto d = [this](size_t i) -> const QColor& { return this->colors_[i]; };")?;
+        for item in flat_layout {
+            let FlatLayoutItem::Struct { name, fields } = item else {
+                panic!("Top level item not struct");
+            };
 
-    let flattened_layout = layout.flatten();
-    for item in flattened_layout.iter() {
-        let FlatLayoutItem::Struct { name, fields } = item else {
-            panic!("Top level item not struct");
-        };
+            p.block(&format!("this->{name} = {{"), "};", |p| {
+                for field in fields {
+                    print_field(p, field, options.designated_init)?;
+                }
+                Ok(())
+            })?;
+        }
+        p.write_line("this->reset();")?;
+        if options.qml {
+            p.write_line("emit this->colorsChanged();")?;
+        }
+        Ok(())
+    })?;
 
-        writeln!(p, "this->{name} = {{")?;
-        p.indent();
-        for field in fields {
-            print_field(p, field)?;
+    p.block(&format!("void {class}::reset() {{"), "}", |p| {
+        // a zero-length array isn't valid C++, and there's nothing to reset
+        if options.color_array && layout::id_count(flat_layout) > 0 {
+            return write_color_array(
+                p,
+                flat_layout,
+                theme,
+                options.preserve_case,
+            );
         }
-        p.dedent();
-        writeln!(p, "}};")?;
-    }
-    p.write_line("this->reset();")?;
+        for item in flat_layout {
+            let FlatLayoutItem::Struct { name, fields } = item else {
+                panic!("Top level item not struct");
+            };
+            for field in fields {
+                reset_field(p, name, theme, options, field)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    p.block(
+        &format!(
+            "bool {class}::setColor(const QByteArray &name, QColor color) {{"
+        ),
+        "}",
+        |p| {
+            p.write_line(
+                "return this->setColor(QLatin1String(name.constData(), name.size()), color);",
+            )
+        },
+    )?;
 
-    p.dedent();
-    p.write_line("}")?;
+    p.block(
+        &format!("bool {class}::setColor(QLatin1String name, QColor color) {{"),
+        "}",
+        |p| {
+            p.write_line("auto idx = getDataIndex(name);")?;
+            p.write_line("if (idx < 0) return false;")?;
+            p.write_line("this->colors_[idx] = color;")?;
+            p.write_line("return true;")
+        },
+    )?;
 
-    p.write_line("void GeneratedTheme::reset() {")?;
-    p.indent();
+    p.block(
+        &format!("void {class}::setColor(ThemeKey key, QColor color) {{"),
+        "}",
+        |p| p.write_line("this->colors_[static_cast<size_t>(key)] = color;"),
+    )?;
 
-    let mut paths = vec![];
-    for item in flattened_layout.iter() {
-        let FlatLayoutItem::Struct { name, fields } = item else {
-            panic!("Top level item not struct");
-        };
-        for field in fields {
-            reset_field(p, &mut paths, name, theme, field)?;
-        }
+    if options.loader {
+        write_loader(p, class)?;
     }
 
-    p.dedent();
-    p.write_line("}")?;
-
-    p.write_line(
-        "bool GeneratedTheme::setColor(const QByteArray &name, QColor color) {",
-    )?;
-    p.indent();
+    if options.all_colors {
+        write_all_colors(p, class, flat_layout, &keys)?;
+    }
 
-    p.write_line("auto idx = getDataIndex(name);")?;
-    p.write_line("if (idx < 0) return false;")?;
-    p.write_line("this->colors_[idx] = color;")?;
-    p.write_line("return true;")?;
+    if options.eq_operators {
+        write_eq_operators(p, class, flat_layout)?;
+    }
 
-    p.dedent();
-    p.write_line("}")?;
+    if options.color_names || options.emit_keys {
+        if !options.emit_keys {
+            p.write_line("#ifndef NDEBUG")?;
+        }
+        let n_ids = layout::id_count(flat_layout);
+        let open =
+            format!("const char *const {class}::colorNames[{n_ids}] = {{");
+        p.block(&open, "};", |p| {
+            // the keys are ordered by their id
+            let mut keys = keys.iter().peekable();
+            for id in 0..n_ids {
+                match keys.next_if(|(_, key_id)| *key_id == id) {
+                    Some((key, _)) => writeln!(p, "\"{key}\",")?,
+                    // the item wasn't generated (`--only`)
+                    None => p.write_line("nullptr,")?,
+                }
+            }
+            Ok(())
+        })?;
+        if !options.emit_keys {
+            p.write_line("#endif")?;
+        }
+    }
 
     p.write_line("} //  namespace chatterino::theme")?;
 
     p.write_line("namespace {")?;
-    p.write_line("int getDataIndex(const QByteArray &name) {")?;
-    p.indent();
-    p.write_line("static const QMap<QByteArray, size_t> dataMap = {")?;
-    p.indent();
-    for (path, value) in paths {
-        writeln!(p, "{{\"{path}\", {value}}},")?;
-    }
-    p.dedent();
-    p.write_line("};")?;
-    p.write_line("return dataMap.value(name, -1);")?;
-    p.dedent();
-    p.write_line("}")?;
+    p.block("int getDataIndex(QLatin1String name) {", "}", |p| {
+        if options.case_insensitive {
+            write_lowercase_key(p, &keys)?;
+        } else {
+            p.write_line("const auto &key = name;")?;
+        }
+        match options.matcher {
+            // a perfect hash needs at least one slot (C++ doesn't allow empty
+            // arrays), so empty layouts use the map
+            Matcher::Phf if !keys.is_empty() => write_phf_lookup(p, &keys),
+            Matcher::Map | Matcher::Phf => write_map_lookup(p, &keys),
+        }
+    })?;
     p.write_line("} //  namespace")?;
 
     Ok(())
 }
 
+/// Defines `key` as the lowercase `name`.
+///
+/// The keys are at most as long as the longest key, so `name` is lowercased
+/// into a buffer on the stack instead of allocating a new string.
+fn write_lowercase_key(
+    p: &mut Printer<impl io::Write>,
+    keys: &[(String, usize)],
+) -> io::Result<()> {
+    let max_len = keys.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    writeln!(p, "if (name.size() > {max_len}) return -1;")?;
+    // C++ doesn't allow empty arrays
+    writeln!(p, "char lower[{}];", max_len.max(1))?;
+    p.write_line("char *out = lower;")?;
+    p.block("for (char c : name) {", "}", |p| {
+        p.write_line("*out++ = c >= 'A' && c <= 'Z' ? c - 'A' + 'a' : c;")
+    })?;
+    p.write_line("const QLatin1String key(lower, name.size());")
+}
+
+fn write_map_lookup(
+    p: &mut Printer<impl io::Write>,
+    keys: &[(String, usize)],
+) -> io::Result<()> {
+    p.block(
+        "static const QMap<QLatin1String, size_t> dataMap = {",
+        "};",
+        |p| {
+            for (key, id) in keys {
+                writeln!(p, "{{QLatin1String(\"{key}\"), {id}}},")?;
+            }
+            Ok(())
+        },
+    )?;
+    p.write_line("return dataMap.value(key, -1);")
+}
+
+/// Looks `name` up in a perfect hash (see [`phf::hash`]).
+fn write_phf_lookup(
+    p: &mut Printer<impl io::Write>,
+    keys: &[(String, usize)],
+) -> io::Result<()> {
+    let phf =
+        phf::build(&keys.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>());
+    let n = keys.len();
+
+    p.block("struct Entry {", "};", |p| {
+        p.write_line("const char *key;")?;
+        p.write_line("int id;")
+    })?;
+    let open = format!("static constexpr uint32_t displacements[{n}] = {{");
+    p.block(&open, "};", |p| {
+        for d in &phf.displacements {
+            writeln!(p, "{d}u,")?;
+        }
+        Ok(())
+    })?;
+    let open = format!("static constexpr Entry entries[{n}] = {{");
+    p.block(&open, "};", |p| {
+        for slot in &phf.slots {
+            let (key, id) = &keys[*slot];
+            writeln!(p, "{{\"{key}\", {id}}},")?;
+        }
+        Ok(())
+    })?;
+    p.block("const auto hash = [&](uint32_t seed) {", "};", |p| {
+        p.write_line("uint32_t h = (2166136261u ^ seed) * 16777619u;")?;
+        p.block("for (char c : key) {", "}", |p| {
+            p.write_line("h = (h ^ static_cast<uint8_t>(c)) * 16777619u;")
+        })?;
+        p.write_line("return h;")
+    })?;
+    writeln!(
+        p,
+        "const auto &entry = entries[hash(displacements[hash(0) % {n}]) % {n}];"
+    )?;
+    p.write_line("return key == QLatin1String(entry.key) ? entry.id : -1;")
+}
+
+/// Writes `loadTheme`, which applies the colors of a c2theme at runtime
+/// (as written by `theme`).
+fn write_loader(
+    p: &mut Printer<impl io::Write>,
+    class: &str,
+) -> io::Result<()> {
+    let open = format!("bool {class}::loadTheme(const QString &path) {{");
+    p.block(&open, "}", |p| {
+        p.write_line("QFile file(path);")?;
+        p.write_line(
+            "if (!file.open(QIODevice::ReadOnly | QIODevice::Text)) return false;",
+        )?;
+        p.write_line("bool inColors = false;")?;
+        p.block("while (!file.atEnd()) {", "}", |p| {
+            p.write_line("const auto line = file.readLine().trimmed();")?;
+            p.write_line(
+                "if (line.isEmpty() || line.startsWith('#')) continue;",
+            )?;
+            p.block("if (line.startsWith('@')) {", "}", |p| {
+                p.write_line("inColors = line == \"@colors\";")?;
+                p.write_line("continue;")
+            })?;
+            p.write_line("const auto eq = line.indexOf('=');")?;
+            p.write_line("if (!inColors || eq < 0) continue;")?;
+            // unknown keys are skipped, so older data files keep working
+            p.write_line("this->setColor(line.left(eq), QColor(QString::fromLatin1(line.mid(eq + 1))));")
+        })?;
+        p.write_line("this->applyChanges();")?;
+        p.write_line("return true;")
+    })
+}
+
+/// Writes `getAllColors`, which maps the key of every color to its applied
+/// value.
+fn write_all_colors(
+    p: &mut Printer<impl io::Write>,
+    class: &str,
+    flat_layout: &[FlatLayoutItem],
+    keys: &[(String, usize)],
+) -> io::Result<()> {
+    let open =
+        format!("QMap<QByteArray, QColor> {class}::getAllColors() const {{");
+    p.block(&open, "}", |p| {
+        p.block("return {", "};", |p| {
+            // both are ordered by the id
+            let mut keys = keys.iter();
+            for_each_field(p, flat_layout, |p, member, _| {
+                let Some((key, _)) = keys.next() else {
+                    panic!("more fields than keys");
+                };
+                writeln!(p, "{{\"{key}\", this->{member}}},")
+            })
+        })
+    })
+}
+
+/// Compares the applied colors (`colors_` only holds the pending ones).
+fn write_eq_operators(
+    p: &mut Printer<impl io::Write>,
+    class: &str,
+    flat_layout: &[FlatLayoutItem],
+) -> io::Result<()> {
+    let open =
+        format!("bool {class}::operator==(const {class} &other) const {{");
+    p.block(&open, "}", |p| {
+        for_each_field(p, flat_layout, |p, member, _| {
+            writeln!(p, "if (this->{member} != other.{member}) return false;")
+        })?;
+        p.write_line("return true;")
+    })?;
+
+    let open =
+        format!("bool {class}::operator!=(const {class} &other) const {{");
+    p.block(&open, "}", |p| p.write_line("return !(*this == other);"))
+}
+
+/// Writes the initializer of `item` (`.name = ` is prepended if `designated`
+/// is set).
 fn print_field(
     p: &mut Printer<impl io::Write>,
     item: &FlatLayoutItem,
+    designated: bool,
 ) -> io::Result<()> {
+    let designator = match designated {
+        true => format!(".{} = ", item.name()),
+        false => String::new(),
+    };
     match item {
-        FlatLayoutItem::Field { id, .. } => writeln!(p, "d({id}),"),
+        FlatLayoutItem::Field { id, .. } => {
+            writeln!(p, "{designator}d({id}),")
+        }
         FlatLayoutItem::Struct { fields, .. } => {
-            writeln!(p, "{{")?;
-            p.indent();
-            for field in fields {
-                print_field(p, field)?;
+            p.block(&format!("{designator}{{"), "},", |p| {
+                for field in fields {
+                    print_field(p, field, designated)?;
+                }
+                Ok(())
+            })
+        }
+    }
+}
+
+/// Writes the default colors as one array (indexed by id) that's copied into
+/// `colors_`.
+fn write_color_array(
+    p: &mut Printer<impl io::Write>,
+    flat_layout: &[FlatLayoutItem],
+    theme: &FlatTheme,
+    preserve_case: bool,
+) -> io::Result<()> {
+    fn collect(
+        colors: &mut Vec<(usize, RGBA)>,
+        prefix: &str,
+        theme: &FlatTheme,
+        item: &FlatLayoutItem,
+        preserve_case: bool,
+    ) {
+        match item {
+            FlatLayoutItem::Field { id, name, default } => {
+                let path = combine_path(
+                    prefix,
+                    name,
+                    DEFAULT_SEPARATOR,
+                    preserve_case,
+                );
+                let Some(color) = theme.rules.get(&path).or(default.as_ref())
+                else {
+                    panic!("no rule for: {path}");
+                };
+                colors.push((*id, *color));
+            }
+            FlatLayoutItem::Struct { name, fields } => {
+                let prefix = combine_path(
+                    prefix,
+                    name,
+                    DEFAULT_SEPARATOR,
+                    preserve_case,
+                );
+                for field in fields {
+                    collect(colors, &prefix, theme, field, preserve_case);
+                }
             }
-            p.dedent();
-            writeln!(p, "}},")
         }
     }
+
+    let mut colors = vec![];
+    for item in flat_layout {
+        collect(&mut colors, "", theme, item, preserve_case);
+    }
+    colors.sort_unstable_by_key(|(id, _)| *id);
+
+    let n_ids = layout::id_count(flat_layout);
+    let open = format!("static constexpr QRgb defaultColors[{n_ids}] = {{");
+    p.block(&open, "};", |p| {
+        // the position in the array is the id
+        let mut colors = colors.iter().peekable();
+        for id in 0..n_ids {
+            let Some((_, color)) =
+                colors.next_if(|(color_id, _)| *color_id == id)
+            else {
+                // the item wasn't generated (`--only`)
+                writeln!(p, "0x00000000, // {id} (unused)")?;
+                continue;
+            };
+            let RGBA {
+                red,
+                green,
+                blue,
+                alpha,
+            } = color;
+            writeln!(
+                p,
+                "0x{alpha:02x}{red:02x}{green:02x}{blue:02x}, // {id}"
+            )?;
+        }
+        Ok(())
+    })?;
+    let open = format!("for (size_t i = 0; i < {n_ids}; i++) {{");
+    p.block(&open, "}", |p| {
+        p.write_line("this->colors_[i] = QColor::fromRgba(defaultColors[i]);")
+    })
 }
 
 fn reset_field(
     p: &mut Printer<impl io::Write>,
-    paths: &mut Vec<(String, usize)>,
     prefix: &str,
     theme: &FlatTheme,
+    options: &CodeOptions,
     item: &FlatLayoutItem,
 ) -> io::Result<()> {
+    let preserve_case = options.preserve_case;
     match item {
-        FlatLayoutItem::Field { id, name } => {
-            let path = combine_path(prefix, name);
-            let Some(color) =  theme.rules.get(&path) else {
+        FlatLayoutItem::Field { id, name, default } => {
+            let path =
+                combine_path(prefix, name, DEFAULT_SEPARATOR, preserve_case);
+            let Some(color) = theme.rules.get(&path).or(default.as_ref())
+            else {
                 panic!("no rule for: {path}");
             };
-            writeln!(
-                p,
-                "this->colors_[{id}] = {{{}, {}, {}, {}}};",
-                color.red, color.green, color.blue, color.alpha
-            )?;
-            paths.push((path, *id));
+            let RGBA {
+                red,
+                green,
+                blue,
+                alpha,
+            } = color;
+            let comment = match theme.sources.get(&path) {
+                // values can span multiple lines
+                Some(source) if options.source_comments => format!(
+                    " // from: {}",
+                    source.split_whitespace().collect::<Vec<_>>().join(" ")
+                ),
+                _ => String::new(),
+            };
+            if options.from_rgb {
+                writeln!(
+                    p,
+                    "this->colors_[{id}] = QColor::fromRgb({red}, {green}, {blue}, {alpha});{comment}",
+                )?;
+            } else {
+                writeln!(
+                    p,
+                    "this->colors_[{id}] = {{{red}, {green}, {blue}, {alpha}}};{comment}",
+                )?;
+            }
         }
         FlatLayoutItem::Struct { name, fields } => {
-            let prefix = combine_path(prefix, name);
+            let prefix =
+                combine_path(prefix, name, DEFAULT_SEPARATOR, preserve_case);
             for field in fields {
-                reset_field(p, paths, &prefix, theme, field)?;
+                reset_field(p, &prefix, theme, options, field)?;
             }
         }
     }
     Ok(())
 }
+
+/// Returns the paths of all fields that have neither a rule nor a default.
+pub fn find_missing_rules(
+    flat_layout: &[FlatLayoutItem],
+    theme: &FlatTheme,
+    preserve_case: bool,
+) -> Vec<String> {
+    fn find(
+        missing: &mut Vec<String>,
+        prefix: &str,
+        theme: &FlatTheme,
+        item: &FlatLayoutItem,
+        preserve_case: bool,
+    ) {
+        match item {
+            FlatLayoutItem::Field { name, default, .. } => {
+                let path = combine_path(
+                    prefix,
+                    name,
+                    DEFAULT_SEPARATOR,
+                    preserve_case,
+                );
+                if default.is_none() && !theme.rules.contains_key(&path) {
+                    missing.push(path);
+                }
+            }
+            FlatLayoutItem::Struct { name, fields } => {
+                let prefix = combine_path(
+                    prefix,
+                    name,
+                    DEFAULT_SEPARATOR,
+                    preserve_case,
+                );
+                for field in fields {
+                    find(missing, &prefix, theme, field, preserve_case);
+                }
+            }
+        }
+    }
+
+    let mut missing = vec![];
+    for item in flat_layout {
+        find(&mut missing, "", theme, item, preserve_case);
+    }
+    missing
+}
@@ -0,0 +1,69 @@
+use std::io;
+
+use crate::model::FlatTheme;
+
+use super::{ChannelOrder, Printer};
+
+pub fn generate(
+    p: &mut Printer<impl io::Write>,
+    theme: &FlatTheme,
+    order: ChannelOrder,
+) -> io::Result<()> {
+    p.block("{", "}", |p| {
+        p.block("\"meta\": {", "},", |p| {
+            let meta = &theme.meta;
+            let optional = [
+                ("name", &meta.name),
+                ("version", &meta.version),
+                ("description", &meta.description),
+            ];
+            writeln!(p, "\"author\": \"{}\",", escape(&meta.author))?;
+            write!(p, "\"icon-set\": \"{}\"", escape(&meta.icon_set))?;
+            for (key, value) in optional {
+                if let Some(value) = value {
+                    p.write(",\n")?;
+                    write!(p, "\"{key}\": \"{}\"", escape(value))?;
+                }
+            }
+            for (key, value) in &meta.extras {
+                p.write(",\n")?;
+                write!(p, "\"{}\": \"{}\"", escape(key), escape(value))?;
+            }
+            p.write("\n")
+        })?;
+
+        p.block("\"colors\": {", "}", |p| {
+            // sort the keys to keep the output diff-friendly
+            let mut rules = theme.rules.iter().collect::<Vec<_>>();
+            rules.sort_unstable_by_key(|(path, _)| *path);
+            for (i, (path, value)) in rules.iter().enumerate() {
+                let sep = if i + 1 == rules.len() { "" } else { "," };
+                writeln!(
+                    p,
+                    "\"{}\": \"{}\"{sep}",
+                    escape(path),
+                    order.format(value)
+                )?;
+            }
+            Ok(())
+        })
+    })
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
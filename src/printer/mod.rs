@@ -1,57 +1,540 @@
-use std::io;
+use std::{io, str::FromStr};
 
+use cssparser::RGBA;
+
+pub mod css;
+pub mod diff;
 pub mod header;
 pub mod r#impl;
+pub mod json;
+pub mod phf;
 pub mod theme;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndentStyle {
+    #[default]
+    Tabs,
+    Spaces(usize),
+}
+
+impl FromStr for IndentStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("tabs") {
+            return Ok(Self::Tabs);
+        }
+        match s.parse() {
+            Ok(n) => Ok(Self::Spaces(n)),
+            Err(_) => {
+                Err(format!("expected 'tabs' or a number of spaces, got '{s}'"))
+            }
+        }
+    }
+}
+
+/// Position of the alpha channel in hex colors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChannelOrder {
+    /// `#aarrggbb` (used by Chatterino).
+    #[default]
+    AlphaFirst,
+    /// `#rrggbbaa` (used by CSS).
+    AlphaLast,
+}
+
+impl ChannelOrder {
+    pub fn format(self, color: &RGBA) -> String {
+        let RGBA {
+            red,
+            green,
+            blue,
+            alpha,
+        } = color;
+        match self {
+            Self::AlphaFirst => {
+                format!("#{alpha:02x}{red:02x}{green:02x}{blue:02x}")
+            }
+            Self::AlphaLast => {
+                format!("#{red:02x}{green:02x}{blue:02x}{alpha:02x}")
+            }
+        }
+    }
+}
+
+/// How `getDataIndex` looks up names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Matcher {
+    /// A 'QMap' from names to indices.
+    #[default]
+    Map,
+    /// A perfect hash computed at generation time (no allocations).
+    Phf,
+}
+
+/// Options affecting the generated C++ code.
+#[derive(Debug, Clone)]
+pub struct CodeOptions {
+    /// Name of the generated class.
+    pub class_name: String,
+    /// Emit `QColor::fromRgb(r, g, b, a)` instead of `{r, g, b, a}`.
+    pub from_rgb: bool,
+    /// Lowercase names before looking them up in `getDataIndex` (the keys
+    /// are already lowercase).
+    pub case_insensitive: bool,
+    /// Make the theme a `QObject` with a `Q_PROPERTY` for every color.
+    pub qml: bool,
+    /// Emit `operator==` and `operator!=` for the theme and its named structs.
+    pub eq_operators: bool,
+    /// Emit `colorNames`, which maps an index to its path (debug builds only).
+    pub color_names: bool,
+    /// Emit `colorNames` in all builds.
+    pub emit_keys: bool,
+    /// Initialize `colors_` in `reset()` from a single `constexpr` array
+    /// instead of one assignment per color.
+    pub color_array: bool,
+    /// Emit `loadTheme`, which applies the colors from a c2theme at runtime.
+    pub loader: bool,
+    /// Keep the case of the keys (they're lowercased otherwise).
+    pub preserve_case: bool,
+    /// Emit `getAllColors`, which maps every key to its applied color.
+    pub all_colors: bool,
+    /// How `getDataIndex` looks up names.
+    pub matcher: Matcher,
+    /// Include the headers the implementation needs (`QColor`, `QMap`, ...).
+    pub default_includes: bool,
+    /// Additional headers to include in the implementation.
+    pub includes: Vec<String>,
+    /// Initialize the structs with designated initializers (C++20).
+    pub designated_init: bool,
+    /// Note how the default colors were written in the style-sheet.
+    pub source_comments: bool,
+}
+
 pub struct Printer<W> {
     writer: W,
     indent: usize,
+    indent_style: IndentStyle,
+    /// Newlines that are only written once something else follows, so
+    /// [`Printer::finish`] can end the output with exactly one.
+    pending_newlines: usize,
+    /// Whether anything other than newlines was written.
+    has_content: bool,
 }
 
-impl<W> Printer<W> {
-    pub fn new(writer: W) -> Self {
-        Self { writer, indent: 0 }
+impl<W: io::Write> Printer<W> {
+    pub fn new(writer: W, indent_style: IndentStyle) -> Self {
+        Self {
+            writer,
+            indent: 0,
+            indent_style,
+            pending_newlines: 0,
+            has_content: false,
+        }
     }
 
-    pub fn indent(&mut self) {
+    fn indent(&mut self) {
         self.indent += 1;
     }
 
-    pub fn dedent(&mut self) {
-        if self.indent == 0 {
-            panic!("Cannot dedent - indent was 0");
+    /// Decreases the indentation.
+    ///
+    /// An unbalanced `dedent` is a bug in the emitter, but shouldn't abort in
+    /// the middle of writing a file, so this only asserts in debug builds.
+    fn dedent(&mut self) {
+        debug_assert!(self.indent > 0, "Cannot dedent - indent was 0");
+        self.indent = self.indent.saturating_sub(1);
+    }
+
+    /// Writes `open` and `close` as separate lines with the `body` indented
+    /// between them.
+    ///
+    /// An empty `close` isn't written, which is used for sections like
+    /// `public:` that only indent what follows.
+    pub fn block(
+        &mut self,
+        open: &str,
+        close: &str,
+        body: impl FnOnce(&mut Self) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.write_line(open)?;
+        self.indent();
+        let res = body(self);
+        self.dedent();
+        res?;
+        if close.is_empty() {
+            return Ok(());
         }
-        self.indent -= 1;
+        self.write_line(close)
     }
-}
 
-impl<W> Printer<W>
-where
-    W: io::Write,
-{
     pub fn write_line(&mut self, line: &str) -> io::Result<()> {
         self.begin_line()?;
         self.write(line)?;
-        writeln!(self.writer)
+        self.write("\n")
     }
 
     pub fn begin_line(&mut self) -> io::Result<()> {
-        let tabs = [b'\t'; 1];
-        for _ in 0..self.indent {
-            self.writer.write_all(&tabs)?;
-        }
-        Ok(())
+        let (c, width) = match self.indent_style {
+            IndentStyle::Tabs => ('\t', 1),
+            IndentStyle::Spaces(n) => (' ', n),
+        };
+        let indentation = c.to_string().repeat(self.indent * width);
+        self.write(&indentation)
     }
 
     pub fn write(&mut self, s: &str) -> io::Result<()> {
-        self.writer.write_all(s.as_bytes())?;
+        let content = s.trim_end_matches('\n');
+        if !content.is_empty() {
+            for _ in 0..self.pending_newlines {
+                self.writer.write_all(b"\n")?;
+            }
+            self.writer.write_all(content.as_bytes())?;
+            self.pending_newlines = 0;
+            self.has_content = true;
+        }
+        self.pending_newlines += s.len() - content.len();
         Ok(())
     }
 
     pub fn write_fmt(&mut self, args: std::fmt::Arguments) -> io::Result<()> {
         self.begin_line()?;
-        self.writer.write_fmt(args)?;
-        Ok(())
+        match args.as_str() {
+            Some(s) => self.write(s),
+            None => self.write(&args.to_string()),
+        }
+    }
+
+    /// Ends the output with exactly one newline (unless nothing was written)
+    /// and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.has_content {
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cssparser::ParserInput;
+
+    use super::*;
+    use crate::{combinator::DEFAULT_SEPARATOR, layout::Layout, parse};
+
+    const LAYOUT: &str = include_str!("../../tests/golden/layout.yml");
+    const THEME: &str = include_str!("../../tests/golden/Theme.css");
+
+    fn options() -> CodeOptions {
+        CodeOptions {
+            class_name: "GeneratedTheme".to_owned(),
+            from_rgb: false,
+            case_insensitive: false,
+            qml: false,
+            eq_operators: false,
+            color_names: false,
+            emit_keys: false,
+            color_array: false,
+            loader: false,
+            preserve_case: false,
+            all_colors: false,
+            matcher: Matcher::Map,
+            default_includes: true,
+            includes: vec![],
+            designated_init: false,
+            source_comments: false,
+        }
+    }
+
+    /// Generates the implementation and header for the golden layout and
+    /// theme.
+    fn generate(options: &CodeOptions) -> (String, String) {
+        let layout = Layout::parse(LAYOUT).unwrap();
+        let flat_layout = layout.flatten(options.preserve_case).unwrap();
+        let mut input = ParserInput::new(THEME);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let theme =
+            parse::parse(&mut parser, None, parse::ParseOptions::STRICT)
+                .unwrap();
+        let (flat, _) = theme
+            .flatten(DEFAULT_SEPARATOR, options.preserve_case, None)
+            .unwrap();
+
+        let mut imp = Printer::new(Vec::new(), IndentStyle::Tabs);
+        r#impl::generate_impl(
+            &mut imp,
+            "GeneratedTheme.hpp",
+            &flat_layout,
+            &flat,
+            options,
+        )
+        .unwrap();
+        let mut header = Printer::new(Vec::new(), IndentStyle::Tabs);
+        header::generate_header(&mut header, &layout, &flat_layout, options)
+            .unwrap();
+        let text = |p: Printer<Vec<u8>>| {
+            String::from_utf8(p.finish().unwrap()).unwrap()
+        };
+        (text(imp), text(header))
+    }
+
+    /// Generates the c2theme and the implementation for a layout with the
+    /// single color `a.b`, which is set to `value`.
+    ///
+    /// Returns the line of the color in both outputs.
+    fn single_color(value: &str, options: &CodeOptions) -> (String, String) {
+        let layout = Layout::parse("layout: { a: { fields: [b] } }").unwrap();
+        let flat_layout = layout.flatten(false).unwrap();
+        let source = format!(
+            r#"@chatterino {{ author: "a"; icon-set: "dark"; }} a {{ b: {value}; }}"#
+        );
+        let mut input = ParserInput::new(&source);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let theme =
+            parse::parse(&mut parser, None, parse::ParseOptions::STRICT)
+                .unwrap();
+        let (flat, _) = theme.flatten(DEFAULT_SEPARATOR, false, None).unwrap();
+
+        let mut c2theme = Printer::new(Vec::new(), IndentStyle::Tabs);
+        theme::generate(&mut c2theme, &flat, None, ChannelOrder::AlphaFirst)
+            .unwrap();
+        let mut imp = Printer::new(Vec::new(), IndentStyle::Tabs);
+        r#impl::generate_impl(
+            &mut imp,
+            "Theme.hpp",
+            &flat_layout,
+            &flat,
+            options,
+        )
+        .unwrap();
+
+        let line_of = |p: Printer<Vec<u8>>, pattern: &str| {
+            let text = String::from_utf8(p.finish().unwrap()).unwrap();
+            let lines = text
+                .lines()
+                .map(str::trim)
+                .filter(|line| line.starts_with(pattern))
+                .collect::<Vec<_>>();
+            match lines[..] {
+                [line] => line.to_owned(),
+                _ => panic!("expected one line starting with {pattern}"),
+            }
+        };
+        let color = if options.color_array {
+            "0x"
+        } else {
+            "this->colors_[0]"
+        };
+        (line_of(c2theme, "a.b="), line_of(imp, color))
+    }
+
+    #[test]
+    fn translucent_colors_keep_their_alpha() {
+        let (c2theme, init) = single_color("#55667788", &options());
+        assert_eq!(c2theme, "a.b=#88556677");
+        assert_eq!(init, "this->colors_[0] = {85, 102, 119, 136};");
+
+        let from_rgb = CodeOptions {
+            from_rgb: true,
+            ..options()
+        };
+        let (_, init) = single_color("#55667788", &from_rgb);
+        assert_eq!(
+            init,
+            "this->colors_[0] = QColor::fromRgb(85, 102, 119, 136);"
+        );
+
+        let color_array = CodeOptions {
+            color_array: true,
+            ..options()
+        };
+        let (_, init) = single_color("#55667788", &color_array);
+        assert_eq!(init, "0x88556677, // 0");
+    }
+
+    #[test]
+    fn transparent() {
+        let (c2theme, init) = single_color("transparent", &options());
+        assert_eq!(c2theme, "a.b=#00000000");
+        assert_eq!(init, "this->colors_[0] = {0, 0, 0, 0};");
+
+        let color_array = CodeOptions {
+            color_array: true,
+            ..options()
+        };
+        let (_, init) = single_color("transparent", &color_array);
+        assert_eq!(init, "0x00000000, // 0");
+    }
+
+    /// Generates the implementation for a layout without any colors.
+    fn generate_empty(options: &CodeOptions) -> String {
+        let layout = Layout::parse("layout: {}").unwrap();
+        let flat_layout = layout.flatten(false).unwrap();
+        let source = r#"@chatterino { author: "a"; icon-set: "dark"; }"#;
+        let mut input = ParserInput::new(source);
+        let mut parser = cssparser::Parser::new(&mut input);
+        let theme =
+            parse::parse(&mut parser, None, parse::ParseOptions::STRICT)
+                .unwrap();
+        let (flat, _) = theme.flatten(DEFAULT_SEPARATOR, false, None).unwrap();
+
+        let mut p = Printer::new(Vec::new(), IndentStyle::Tabs);
+        r#impl::generate_impl(
+            &mut p,
+            "Theme.hpp",
+            &flat_layout,
+            &flat,
+            options,
+        )
+        .unwrap();
+        String::from_utf8(p.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn phf_on_empty_layout_uses_map() {
+        let generate = |matcher| {
+            generate_empty(&CodeOptions {
+                matcher,
+                // the includes differ
+                default_includes: false,
+                ..options()
+            })
+        };
+        let phf = generate(Matcher::Phf);
+        assert!(!phf.contains("displacements"));
+        assert_eq!(phf, generate(Matcher::Map));
+    }
+
+    #[test]
+    fn color_array_on_empty_layout_uses_fields() {
+        let generate = |color_array| {
+            generate_empty(&CodeOptions {
+                color_array,
+                ..options()
+            })
+        };
+        let array = generate(true);
+        assert!(!array.contains("defaultColors"));
+        assert_eq!(array, generate(false));
+    }
+
+    #[test]
+    fn case_insensitive_lookup_doesnt_allocate() {
+        for matcher in [Matcher::Map, Matcher::Phf] {
+            let (imp, _) = generate(&CodeOptions {
+                case_insensitive: true,
+                matcher,
+                ..options()
+            });
+            assert!(!imp.contains("toLower"), "{matcher:?}");
+            // the longest key is tabs.selected.backgrounds.regular
+            assert!(imp.contains("if (name.size() > 33) return -1;"));
+            assert!(imp.contains("char lower[33];"));
+            assert!(
+                imp.contains("const QLatin1String key(lower, name.size());")
+            );
+        }
+    }
+
+    /// The golden files might be checked out with CRLF line endings.
+    fn golden(content: &str) -> String {
+        content.replace("\r\n", "\n")
+    }
+
+    #[test]
+    fn golden_impl_and_header() {
+        let (imp, header) = generate(&options());
+        assert_eq!(
+            imp,
+            golden(include_str!("../../tests/golden/GeneratedTheme.cpp"))
+        );
+        assert_eq!(
+            header,
+            golden(include_str!("../../tests/golden/GeneratedTheme.hpp"))
+        );
+    }
+
+    #[test]
+    fn golden_color_array() {
+        let (imp, _) = generate(&CodeOptions {
+            color_array: true,
+            ..options()
+        });
+        assert_eq!(
+            imp,
+            golden(include_str!("../../tests/golden/GeneratedTheme.array.cpp"))
+        );
+
+        // every color is commented with its id, which has to be its index
+        let ids = imp
+            .lines()
+            .filter(|line| line.trim_start().starts_with("0x"))
+            .map(|line| line.rsplit("// ").next().unwrap().parse().unwrap())
+            .collect::<Vec<usize>>();
+        assert_eq!(ids, (0..10).collect::<Vec<_>>());
+    }
+
+    /// Returns the trimmed lines after the first line containing `start` up
+    /// to the closing `};`.
+    fn table<'a>(text: &'a str, start: &str) -> Vec<&'a str> {
+        text.lines()
+            .skip_while(|line| !line.contains(start))
+            .skip(1)
+            .map(str::trim)
+            .take_while(|line| *line != "};")
+            .collect()
+    }
+
+    #[test]
+    fn emitted_keys_match_names_and_lookup() {
+        for matcher in [Matcher::Map, Matcher::Phf] {
+            let (imp, header) = generate(&CodeOptions {
+                emit_keys: true,
+                matcher,
+                ..options()
+            });
+
+            let names = table(&imp, "colorNames[")
+                .into_iter()
+                .map(|line| line.trim_end_matches(',').trim_matches('"'))
+                .collect::<Vec<_>>();
+            assert!(!names.is_empty());
+
+            // `key_name = id,`
+            let keys = table(&header, "enum class ThemeKey")
+                .into_iter()
+                .map(|line| {
+                    let (key, id) =
+                        line.trim_end_matches(',').split_once(" = ").unwrap();
+                    (key, id.parse::<usize>().unwrap())
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(keys.len(), names.len(), "{matcher:?}");
+            for (key, id) in keys {
+                assert_eq!(key, names[id].replace('.', "_"), "{matcher:?}");
+            }
+
+            // `{QLatin1String("name"), id},` or `{"name", id},`
+            let mut lookup = imp
+                .lines()
+                .map(str::trim)
+                .filter_map(|line| {
+                    let entry = line.strip_prefix('{')?.strip_suffix("},")?;
+                    let (name, id) = entry.rsplit_once(", ")?;
+                    let name = name
+                        .trim_start_matches("QLatin1String(")
+                        .trim_end_matches(')')
+                        .trim_matches('"');
+                    Some((id.parse::<usize>().ok()?, name))
+                })
+                .collect::<Vec<_>>();
+            lookup.sort_unstable();
+            assert_eq!(
+                lookup,
+                names.iter().copied().enumerate().collect::<Vec<_>>(),
+                "{matcher:?}"
+            );
+        }
     }
 }
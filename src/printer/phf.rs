@@ -0,0 +1,72 @@
+use std::cmp::Reverse;
+
+const FNV_OFFSET: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// FNV-1a of `key`, with `seed` mixed into the offset.
+///
+/// The generated code has to compute this the same way.
+pub fn hash(seed: u32, key: &[u8]) -> u32 {
+    let mut h = (FNV_OFFSET ^ seed).wrapping_mul(FNV_PRIME);
+    for b in key {
+        h = (h ^ u32::from(*b)).wrapping_mul(FNV_PRIME);
+    }
+    h
+}
+
+/// A minimal perfect hash (hash and displace).
+pub struct Phf {
+    /// Seeds for the second hash, indexed by `hash(0, key) % len`.
+    pub displacements: Vec<u32>,
+    /// Indices into the keys, indexed by
+    /// `hash(displacements[..], key) % len`.
+    pub slots: Vec<usize>,
+}
+
+/// Builds a perfect hash for `keys`, which must be unique and non-empty.
+pub fn build(keys: &[&str]) -> Phf {
+    debug_assert!(!keys.is_empty(), "a perfect hash needs at least one key");
+    let n = keys.len();
+    let slot_of = |seed, key: &str| hash(seed, key.as_bytes()) as usize % n;
+
+    let mut buckets = vec![vec![]; n];
+    for (i, key) in keys.iter().enumerate() {
+        buckets[slot_of(0, key)].push(i);
+    }
+    // large buckets are the hardest to place, so they go first
+    let mut order = (0..n).collect::<Vec<_>>();
+    order.sort_by_key(|b| Reverse(buckets[*b].len()));
+
+    let mut displacements = vec![0; n];
+    let mut slots = vec![None; n];
+    for bucket in order {
+        let bucket_keys = &buckets[bucket];
+        if bucket_keys.is_empty() {
+            break;
+        }
+        for seed in 1.. {
+            let targets = bucket_keys
+                .iter()
+                .map(|i| slot_of(seed, keys[*i]))
+                .collect::<Vec<_>>();
+            let fits = targets.iter().enumerate().all(|(i, target)| {
+                slots[*target].is_none() && !targets[..i].contains(target)
+            });
+            if fits {
+                for (i, target) in bucket_keys.iter().zip(targets) {
+                    slots[target] = Some(*i);
+                }
+                displacements[bucket] = seed;
+                break;
+            }
+        }
+    }
+
+    Phf {
+        displacements,
+        slots: slots
+            .into_iter()
+            .map(|s| s.expect("every slot is used"))
+            .collect(),
+    }
+}
@@ -2,22 +2,43 @@ use std::io;
 
 use crate::model::FlatTheme;
 
-use super::Printer;
+use super::{ChannelOrder, Printer};
 
+/// Writes `theme` as a c2theme.
+///
+/// If a `comment` is given, it's written as `# <comment>` before `@meta`.
 pub fn generate(
     p: &mut Printer<impl io::Write>,
     theme: &FlatTheme,
+    comment: Option<&str>,
+    order: ChannelOrder,
 ) -> io::Result<()> {
+    if let Some(comment) = comment {
+        for line in comment.lines() {
+            writeln!(p, "# {line}")?;
+        }
+    }
     p.write_line("@meta")?;
     writeln!(p, "author={}", theme.meta.author)?;
     writeln!(p, "iconset={}", theme.meta.icon_set)?;
+    if let Some(name) = &theme.meta.name {
+        writeln!(p, "name={name}")?;
+    }
+    if let Some(version) = &theme.meta.version {
+        writeln!(p, "version={version}")?;
+    }
+    if let Some(description) = &theme.meta.description {
+        writeln!(p, "description={description}")?;
+    }
+    for (key, value) in &theme.meta.extras {
+        writeln!(p, "{key}={value}")?;
+    }
     p.write_line("@colors")?;
-    for (color, value) in theme.rules.iter() {
-        writeln!(
-            p,
-            "{color}=#{:02x}{:02x}{:02x}{:02x}",
-            value.alpha, value.red, value.green, value.blue,
-        )?;
+    // sorted, so the output is the same on every run
+    let mut rules = theme.rules.iter().collect::<Vec<_>>();
+    rules.sort_unstable_by_key(|(color, _)| *color);
+    for (color, value) in rules {
+        writeln!(p, "{color}={}", order.format(value))?;
     }
     Ok(())
 }
@@ -0,0 +1,90 @@
+#include "GeneratedTheme.hpp"
+#include <QColor>
+#include <QString>
+#include <QByteArray>
+#include <QMap>
+#include <cstring>
+
+namespace {
+	int getDataIndex(QLatin1String name);
+} //  namespace
+namespace chatterino::theme {
+GeneratedTheme::GeneratedTheme() {
+	static_assert(sizeof(colors_) / sizeof(colors_[0]) == 10, "the header and the implementation were generated from different layouts");
+	this->reset();
+	this->applyChanges();
+}
+void GeneratedTheme::applyChanges() {
+	const auto d = [this](size_t i) -> const QColor& { return this->colors_[i]; };
+	this->colors = {
+		d(0),
+		d(1),
+		d(2),
+	};
+	this->tabs = {
+		d(3),
+		{
+			d(4),
+			{
+				d(5),
+				d(6),
+			},
+		},
+		{
+			d(7),
+			{
+				d(8),
+				d(9),
+			},
+		},
+	};
+	this->reset();
+}
+void GeneratedTheme::reset() {
+	static constexpr QRgb defaultColors[10] = {
+		0xff010203, // 0
+		0xff040506, // 1
+		0x80070809, // 2
+		0xff0a0b0c, // 3
+		0xff0d0e0f, // 4
+		0xff101112, // 5
+		0xff131415, // 6
+		0xff161718, // 7
+		0xff191a1b, // 8
+		0xff131415, // 9
+	};
+	for (size_t i = 0; i < 10; i++) {
+		this->colors_[i] = QColor::fromRgba(defaultColors[i]);
+	}
+}
+bool GeneratedTheme::setColor(const QByteArray &name, QColor color) {
+	return this->setColor(QLatin1String(name.constData(), name.size()), color);
+}
+bool GeneratedTheme::setColor(QLatin1String name, QColor color) {
+	auto idx = getDataIndex(name);
+	if (idx < 0) return false;
+	this->colors_[idx] = color;
+	return true;
+}
+void GeneratedTheme::setColor(ThemeKey key, QColor color) {
+	this->colors_[static_cast<size_t>(key)] = color;
+}
+} //  namespace chatterino::theme
+namespace {
+int getDataIndex(QLatin1String name) {
+	const auto &key = name;
+	static const QMap<QLatin1String, size_t> dataMap = {
+		{QLatin1String("colors.accent"), 0},
+		{QLatin1String("colors.palette0"), 1},
+		{QLatin1String("colors.palette1"), 2},
+		{QLatin1String("tabs.border"), 3},
+		{QLatin1String("tabs.regular.text"), 4},
+		{QLatin1String("tabs.regular.backgrounds.regular"), 5},
+		{QLatin1String("tabs.regular.backgrounds.hover"), 6},
+		{QLatin1String("tabs.selected.text"), 7},
+		{QLatin1String("tabs.selected.backgrounds.regular"), 8},
+		{QLatin1String("tabs.selected.backgrounds.hover"), 9},
+	};
+	return dataMap.value(key, -1);
+}
+} //  namespace
@@ -0,0 +1,85 @@
+#include "GeneratedTheme.hpp"
+#include <QColor>
+#include <QString>
+#include <QByteArray>
+#include <QMap>
+#include <cstring>
+
+namespace {
+	int getDataIndex(QLatin1String name);
+} //  namespace
+namespace chatterino::theme {
+GeneratedTheme::GeneratedTheme() {
+	static_assert(sizeof(colors_) / sizeof(colors_[0]) == 10, "the header and the implementation were generated from different layouts");
+	this->reset();
+	this->applyChanges();
+}
+void GeneratedTheme::applyChanges() {
+	const auto d = [this](size_t i) -> const QColor& { return this->colors_[i]; };
+	this->colors = {
+		d(0),
+		d(1),
+		d(2),
+	};
+	this->tabs = {
+		d(3),
+		{
+			d(4),
+			{
+				d(5),
+				d(6),
+			},
+		},
+		{
+			d(7),
+			{
+				d(8),
+				d(9),
+			},
+		},
+	};
+	this->reset();
+}
+void GeneratedTheme::reset() {
+	this->colors_[0] = {1, 2, 3, 255};
+	this->colors_[1] = {4, 5, 6, 255};
+	this->colors_[2] = {7, 8, 9, 128};
+	this->colors_[3] = {10, 11, 12, 255};
+	this->colors_[4] = {13, 14, 15, 255};
+	this->colors_[5] = {16, 17, 18, 255};
+	this->colors_[6] = {19, 20, 21, 255};
+	this->colors_[7] = {22, 23, 24, 255};
+	this->colors_[8] = {25, 26, 27, 255};
+	this->colors_[9] = {19, 20, 21, 255};
+}
+bool GeneratedTheme::setColor(const QByteArray &name, QColor color) {
+	return this->setColor(QLatin1String(name.constData(), name.size()), color);
+}
+bool GeneratedTheme::setColor(QLatin1String name, QColor color) {
+	auto idx = getDataIndex(name);
+	if (idx < 0) return false;
+	this->colors_[idx] = color;
+	return true;
+}
+void GeneratedTheme::setColor(ThemeKey key, QColor color) {
+	this->colors_[static_cast<size_t>(key)] = color;
+}
+} //  namespace chatterino::theme
+namespace {
+int getDataIndex(QLatin1String name) {
+	const auto &key = name;
+	static const QMap<QLatin1String, size_t> dataMap = {
+		{QLatin1String("colors.accent"), 0},
+		{QLatin1String("colors.palette0"), 1},
+		{QLatin1String("colors.palette1"), 2},
+		{QLatin1String("tabs.border"), 3},
+		{QLatin1String("tabs.regular.text"), 4},
+		{QLatin1String("tabs.regular.backgrounds.regular"), 5},
+		{QLatin1String("tabs.regular.backgrounds.hover"), 6},
+		{QLatin1String("tabs.selected.text"), 7},
+		{QLatin1String("tabs.selected.backgrounds.regular"), 8},
+		{QLatin1String("tabs.selected.backgrounds.hover"), 9},
+	};
+	return dataMap.value(key, -1);
+}
+} //  namespace
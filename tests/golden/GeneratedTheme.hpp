@@ -0,0 +1,55 @@
+#include <QColor>
+#include <QByteArray>
+#include <QString>
+#include <cstddef>
+
+namespace chatterino::theme {
+class GeneratedTheme {
+public:
+	static constexpr int GENERATOR_VERSION = 200;
+	static constexpr const char *GENERATOR_VERSION_STRING = "0.2.0";
+	enum class ThemeKey : size_t {
+		colors_accent = 0,
+		colors_palette0 = 1,
+		colors_palette1 = 2,
+		tabs_border = 3,
+		tabs_regular_text = 4,
+		tabs_regular_backgrounds_regular = 5,
+		tabs_regular_backgrounds_hover = 6,
+		tabs_selected_text = 7,
+		tabs_selected_backgrounds_regular = 8,
+		tabs_selected_backgrounds_hover = 9,
+	};
+	struct TabColors {
+		QColor text;
+		
+		struct {
+			QColor regular;
+			QColor hover;
+		} backgrounds;
+	};
+	
+	struct {
+		QColor accent;
+		QColor palette0;
+		QColor palette1;
+	} colors;
+	
+	struct {
+		QColor border;
+		TabColors regular;
+		TabColors selected;
+	} tabs;
+	GeneratedTheme();
+
+protected:
+	bool setColor(const QByteArray &name, QColor color);
+	bool setColor(QLatin1String name, QColor color);
+	void setColor(ThemeKey key, QColor color);
+	void reset();
+	void applyChanges();
+
+private:
+	QColor colors_[10];
+};
+}  // namespace chatterino::theme